@@ -31,8 +31,10 @@ struct MyVertexOutput {
 
     @location(2) bary: vec3<f32>,
     @location(3) mtls: vec3<f32>,  // material texture ids. u32
+    @location(4) ao: f32,  // baked ambient occlusion. 1: open
+    @location(5) light: vec2<f32>,  // baked (sky, block) light levels in [0, 1]
 
-    @location(5) @interpolate(flat) instance_index: u32,
+    @location(6) @interpolate(flat) instance_index: u32,
 }
 
 @vertex
@@ -50,8 +52,20 @@ fn vertex(
     out.world_normal = mesh_functions::mesh_normal_local_to_world(in.normal, inst_idx); 
     out.instance_index = inst_idx;
 
+#ifdef VERTEX_COLORS
+    // one-hot corner of the vertex in the triangle. the 3 vertices of a triangle are different corners. (indexed vertices)
+    let bary = in.color.rgb;
+    out.ao = in.color.a;
+#else
     let vi = vtx_index % 3u;
     let bary = vec3<f32>(f32(vi == 0u), f32(vi == 1u), f32(vi == 2u));
+    out.ao = 1.0;
+#endif
+#ifdef VERTEX_UVS_B
+    out.light = in.uv_b;
+#else
+    out.light = vec2<f32>(1.0);
+#endif
     out.bary = bary;
     out.mtls = bary * vec3<f32>(in.uv.x, in.uv.x, in.uv.x);
 
@@ -88,7 +102,7 @@ fn _vec3_max_idx(v: vec3<f32>) -> i32 {
 }
 
 fn triplanar_uv(mtl_id: f32, _p: vec3<f32>) -> array<vec2<f32>, 3> {
-    let num_mtls = 24.0;
+    let num_mtls = 26.0;
 	let bias = 0.001 / num_mtls;  // intoduce Epsilon to fix Mipmap Error (and Float-point Error) on Tex Boundary 0.02
     let tex_mul_x = 1.0 / num_mtls;
     let tex_add_x = mtl_id / num_mtls;
@@ -114,6 +128,11 @@ fn triplanar_sample(
         textureSample(tex, _sampler, uvs[2]) * blend.z;
 }
 
+// each light level is 0.8x as bright as the next, level 0 is dark. as same as `lighting::brightness`.
+fn light_brightness(level: f32) -> f32 {
+    return select(0.0, pow(0.8, (1.0 - level) * 15.0), level > 0.0);
+}
+
 fn _normal_sample(uv: vec2<f32>) -> vec3<f32> {
     return textureSample(tex_normal, _sampler, uv).rgb * 2.0 - 1.0;
 }
@@ -190,7 +209,8 @@ fn fragment(
     vert_out.instance_index = in.instance_index;
     var pbr_in = pbr_fragment::pbr_input_from_vertex_output(vert_out, is_front, false);
 
-    pbr_in.material.base_color = base_color; 
+    // AO and the sky light darken both direct and ambient light, caves are not dark without shadows.
+    pbr_in.material.base_color = vec4<f32>(base_color.rgb * in.ao * light_brightness(in.light.x), base_color.a);
     pbr_in.material.perceptual_roughness = roughness;
     pbr_in.material.reflectance = 1.0 - roughness;
     // pbr_in.material.ior = 0.99;
@@ -199,6 +219,10 @@ fn fragment(
     
     var color = pbr_functions::apply_pbr_lighting(pbr_in);
 
+    // block light e.g. lanterns, warm. added after the lighting, regardless of the sun and the exposure.
+    let block_light = base_color.rgb * in.ao * light_brightness(in.light.y) * vec3<f32>(1.0, 0.85, 0.6);
+    color = vec4<f32>(color.rgb + block_light, color.a);
+
     pbr_in.material.flags |= pbr_types::STANDARD_MATERIAL_FLAGS_FOG_ENABLED_BIT;  // enable fog
     color = pbr_functions::main_pass_post_lighting_processing(pbr_in, color);
     
@@ -0,0 +1,161 @@
+// Chunk cells layout: the flat `[Cell; 4096]` of before, vs `PalettedCells`.
+//
+// Chunks only store PalettedCells now, so generate_chunk and generate_chunk_mesh are measured end-to-end on it,
+// and their cell accesses are replayed on both layouts:
+//   generation: sets every cell in local_idx order, then compacts.
+//   meshing:    gets every cell and its 6 neighbors.
+//   edits:      sets surface cells to new isovalues, e.g. brushes and fluids. the palette grows and compacts.
+//
+// cargo bench --bench chunk_layout
+
+use bevy::math::{ivec3, IVec3};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use ethertia::voxel::{
+    meshgen::{MeshGen, VertexBuffer},
+    Cell, Chunk, PalettedCells, WorldGen,
+};
+
+const NUM_CELLS: usize = 16 * 16 * 16;
+
+struct FlatCells(Box<[Cell; NUM_CELLS]>);
+
+impl FlatCells {
+    fn get(&self, idx: usize) -> &Cell {
+        &self.0[idx]
+    }
+
+    fn set(&mut self, idx: usize, cell: &Cell) {
+        self.0[idx] = *cell;
+    }
+}
+
+// a chunk crossing the terrain surface, the most distinct cells (isovalues) and mesh.
+fn surface_chunk(worldgen: &WorldGen) -> Chunk {
+    let (x, y) = (0..256)
+        .step_by(16)
+        .find_map(|x| Some((x, worldgen.surface_y(x + 8, 8)?)))
+        .expect("no surface");
+    let mut chunk = Chunk::new(ivec3(x, y.div_euclid(Chunk::SIZE) * Chunk::SIZE, 0));
+    worldgen.generate_chunk(&mut chunk);
+    chunk
+}
+
+fn cells_of(chunk: &Chunk) -> Vec<Cell> {
+    (0..NUM_CELLS as i32).map(|i| *chunk.get_cell(Chunk::local_idx_pos(i))).collect()
+}
+
+// sum of isovalues of each cell and its neighbors inside the chunk.
+fn read_neighbors<'a>(get: impl Fn(usize) -> &'a Cell) -> f32 {
+    let mut sum = 0.0;
+    for i in 0..NUM_CELLS as i32 {
+        let lp = Chunk::local_idx_pos(i);
+        sum += get(i as usize).isovalue();
+        for d in [IVec3::X, IVec3::NEG_X, IVec3::Y, IVec3::NEG_Y, IVec3::Z, IVec3::NEG_Z] {
+            if Chunk::is_localpos(lp + d) {
+                sum += get(Chunk::local_idx(lp + d)).isovalue();
+            }
+        }
+    }
+    sum
+}
+
+fn bench_generate(c: &mut Criterion) {
+    let worldgen = WorldGen::new(100);
+    let chunk = surface_chunk(&worldgen);
+    let cells = cells_of(&chunk);
+    println!("surface chunk {}: {} palette entries", chunk.chunkpos, chunk.cells().palette().len());
+
+    let mut group = c.benchmark_group("generate");
+    group.bench_function("generate_chunk", |b| {
+        b.iter_batched(
+            || Chunk::new(chunk.chunkpos),
+            |mut c| worldgen.generate_chunk(&mut c),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("flat", |b| {
+        b.iter(|| {
+            let mut flat = FlatCells(Box::new([Cell::default(); NUM_CELLS]));
+            for (i, cell) in cells.iter().enumerate() {
+                flat.set(i, cell);
+            }
+            flat
+        })
+    });
+    group.bench_function("paletted", |b| {
+        b.iter(|| {
+            let mut paletted = PalettedCells::default();
+            for (i, cell) in cells.iter().enumerate() {
+                paletted.set(i, cell);
+            }
+            paletted.compact();
+            paletted
+        })
+    });
+    group.finish();
+}
+
+fn bench_mesh(c: &mut Criterion) {
+    let chunk = surface_chunk(&WorldGen::new(100));
+    let cells = cells_of(&chunk);
+    let flat = FlatCells(Box::new(cells.clone().try_into().unwrap()));
+
+    let mut group = c.benchmark_group("mesh");
+    let mut vbuf = VertexBuffer::default();
+    group.bench_function("generate_chunk_mesh", |b| {
+        b.iter(|| {
+            vbuf.clear();
+            MeshGen::generate_chunk_mesh(&mut vbuf, &chunk);
+        })
+    });
+    group.bench_function("flat", |b| b.iter(|| read_neighbors(|i| flat.get(i))));
+    group.bench_function("paletted", |b| b.iter(|| read_neighbors(|i| chunk.cells().get(i))));
+    group.finish();
+}
+
+fn bench_edit(c: &mut Criterion) {
+    let chunk = surface_chunk(&WorldGen::new(100));
+    let cells = cells_of(&chunk);
+
+    // surface cells, non-zero isovalue, and the new values set to them.
+    let surface: Vec<usize> = (0..NUM_CELLS).filter(|i| cells[*i].isovalue().abs() < 0.9).collect();
+    let mut rng = StdRng::seed_from_u64(0);
+    let edits: Vec<(usize, Cell)> = (0..1024)
+        .map(|_| {
+            let i = surface[rng.gen_range(0..surface.len())];
+            (i, Cell::new(cells[i].tex_id, cells[i].shape_id, rng.gen_range(-1.0..1.0)))
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("edit");
+    group.bench_function("flat", |b| {
+        b.iter_batched(
+            || FlatCells(Box::new(cells.clone().try_into().unwrap())),
+            |mut flat| {
+                for (i, cell) in edits.iter() {
+                    flat.set(*i, black_box(cell));
+                }
+                flat
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("paletted", |b| {
+        b.iter_batched(
+            || chunk.cells().clone(),
+            |mut paletted| {
+                for (i, cell) in edits.iter() {
+                    paletted.set(*i, black_box(cell));
+                }
+                paletted
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_generate, bench_mesh, bench_edit);
+criterion_main!(benches);
@@ -10,6 +10,7 @@ fn main() {
                 MinimalPlugins.set(bevy::app::ScheduleRunnerPlugin::run_loop(frame_time)), // fixed fps
             )
             .add_plugins(bevy::log::LogPlugin::default())
+            .add_plugins(bevy::asset::AssetPlugin::default()) // worldgen presets
             .add_plugins(ethertia::server::prelude::DedicatedServerPlugin)
             .run();
     }
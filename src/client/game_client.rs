@@ -17,7 +17,7 @@ use crate::{client::prelude::*, server::prelude::IntegratedServerPlugin};
 use crate::item::{Inventory, ItemPlugin};
 use crate::net::{CPacket, ClientNetworkPlugin, RenetClientHelper};
 use crate::util::TimeIntervals;
-use crate::voxel::ClientVoxelPlugin;
+use crate::voxel::{Cell, ChunkSystem, ClientChunkSystem, ClientVoxelPlugin};
 
 pub struct ClientGamePlugin;
 
@@ -85,6 +85,7 @@ impl Plugin for ClientGamePlugin {
 
         // Debug
         {
+            app.add_plugins(bevy_common_assets::json::JsonAssetPlugin::<crate::wfc::Tileset>::new(&["tileset.json"]));
             app.add_systems(Update, wfc_test);
 
             // Draw Basis
@@ -153,63 +154,85 @@ fn wfc_test(
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
+    tilesets: Res<Assets<crate::wfc::Tileset>>,
 
     mut ctx: bevy_egui::EguiContexts,
     query_wfc: Query<Entity, With<WfcTest>>,
 
-    mut tx_templ_name: Local<String>,
+    mut tx_tileset: Local<String>,
+    mut tx_seed: Local<String>,
+    mut tileset: Local<Handle<crate::wfc::Tileset>>,
+    mut regen: Local<bool>,
 ) {
+    if tx_tileset.is_empty() {
+        *tx_tileset = "test/comp/circuit/circuit.tileset.json".into();
+    }
     bevy_egui::egui::Window::new("WFC").show(ctx.ctx_mut(), |ui| {
-        ui.text_edit_singleline(&mut *tx_templ_name);
+        ui.horizontal(|ui| {
+            ui.label("Tileset:");
+            ui.text_edit_singleline(&mut *tx_tileset);
+        });
+        ui.horizontal(|ui| {
+            ui.label("Seed:");
+            ui.text_edit_singleline(&mut *tx_seed);
+        });
 
         if ui.btn("ReGen").clicked() {
-            for e_wfc in query_wfc.iter() {
-                cmds.entity(e_wfc).despawn_recursive();
-            }
-
-            use crate::wfc::*;
-            let mut wfc = WFC::new();
-            wfc.push_pattern("0".into(), [0; 6], false, false);
-            wfc.push_pattern("1".into(), [1; 6], false, false);
-            wfc.push_pattern("2".into(), [0, 2, 0, 0, 0, 0], true, false);
-            wfc.push_pattern("3".into(), [3, 3, 0, 0, 0, 0], true, false);
-            wfc.push_pattern("4".into(), [1, 2, 0, 0, 4, 4], true, false);
-            wfc.push_pattern("5".into(), [4, 0, 0, 0, 4, 0], true, false);
-            wfc.push_pattern("6".into(), [2, 2, 0, 0, 0, 0], true, false);
-            wfc.push_pattern("7".into(), [2, 2, 0, 0, 3, 3], true, false);
-            wfc.push_pattern("8".into(), [0, 0, 0, 0, 3, 2], true, false);
-            wfc.push_pattern("9".into(), [2, 2, 0, 0, 2, 0], true, false);
-            wfc.push_pattern("10".into(), [2, 2, 0, 0, 2, 2], true, false);
-            wfc.push_pattern("11".into(), [0, 2, 0, 0, 2, 0], true, false);
-            wfc.push_pattern("12".into(), [2, 2, 0, 0, 0, 0], true, false);
-            wfc.init_tiles(IVec3::new(15, 1, 15));
-
-            wfc.run();
-
-            for tile in wfc.tiles.iter() {
-                if tile.entropy() == 0 {
-                    continue; // ERROR
-                }
-                let pat = &wfc.all_patterns[tile.possib[0] as usize];
-
-                cmds.spawn((
-                    PbrBundle {
-                        mesh: meshes.add(Plane3d::new(Vec3::Y)),
-                        material: materials.add(StandardMaterial {
-                            base_color_texture: Some(asset_server.load(format!("test/comp/circuit{}/{}.png", &*tx_templ_name, pat.name))),
-                            unlit: true,
-                            ..default()
-                        }),
-                        transform: Transform::from_translation(tile.pos.as_vec3() + (Vec3::ONE - Vec3::Y) * 0.5)
-                            .with_scale(Vec3::ONE * 0.49 * if pat.is_flipped { -1.0 } else { 1.0 })
-                            .with_rotation(Quat::from_axis_angle(Vec3::Y, f32::to_radians(pat.rotation as f32 * 90.0))),
-                        ..default()
-                    },
-                    WfcTest,
-                ));
-            }
+            *tileset = asset_server.load(tx_tileset.clone());
+            *regen = true;
         }
     });
+    if !*regen {
+        return;
+    }
+    // wait for the tileset loaded.
+    let Some(tileset) = tilesets.get(&*tileset) else {
+        if matches!(asset_server.load_state(&*tileset), bevy::asset::LoadState::Failed) {
+            warn!("Failed to load WFC tileset {}", &*tx_tileset);
+            *regen = false;
+        }
+        return;
+    };
+    *regen = false;
+
+    for e_wfc in query_wfc.iter() {
+        cmds.entity(e_wfc).despawn_recursive();
+    }
+
+    use crate::wfc::*;
+    let mut wfc = WFC::new().with_seed(crate::voxel::WorldGen::parse_seed(&tx_seed));
+    wfc.push_tileset(tileset);
+    wfc.init_tiles(IVec3::new(15, 1, 15));
+
+    if let Err(err) = wfc.run() {
+        warn!("{}", err);
+    }
+
+    for tile in wfc.tiles.iter() {
+        if tile.entropy() == 0 {
+            continue; // contradiction
+        }
+        let pat = &wfc.all_patterns[tile.possib[0] as usize];
+        let Some(asset) = tileset.get(&pat.name).and_then(|t| t.asset.clone()) else {
+            continue;
+        };
+
+        cmds.spawn((
+            PbrBundle {
+                mesh: meshes.add(Plane3d::new(Vec3::Y)),
+                material: materials.add(StandardMaterial {
+                    base_color_texture: Some(asset_server.load(asset)),
+                    unlit: true,
+                    ..default()
+                }),
+                transform: Transform::from_translation(tile.pos.as_vec3() + (Vec3::ONE - Vec3::Y) * 0.5)
+                    .with_scale(Vec3::ONE * 0.49 * if pat.is_flipped { -1.0 } else { 1.0 })
+                    .with_rotation(Quat::from_axis_angle(Vec3::Y, f32::to_radians(pat.rotation as f32 * 90.0))),
+                ..default()
+            },
+            WfcTest,
+        ));
+    }
 }
 
 fn on_world_init(
@@ -283,6 +306,8 @@ fn tick_world(
     mut last_player_pos: Local<Vec3>,
 
     mut query_fog: Query<&mut FogSettings>,
+    query_cam: Query<&Transform, (With<CharacterControllerCamera>, Without<Sun>)>,
+    chunk_sys: Option<Res<ClientChunkSystem>>,
     cli: Res<ClientInfo>,
 ) {
     // worldinfo.tick_timer.tick(time.delta());
@@ -340,6 +365,19 @@ fn tick_world(
         fog.falloff = FogFalloff::from_visibility_squared(cli.sky_fog_visibility / 4.0);
     }
 
+    // Underwater. the camera is under the fluid surface of its cell, or the cell above is fluid too.
+    if let (Ok(cam_trans), Some(chunk_sys)) = (query_cam.get_single(), chunk_sys) {
+        let p = cam_trans.translation;
+        let vp = p.floor().as_ivec3();
+        if let Some(c) = chunk_sys.get_cell(vp).filter(Cell::is_fluid) {
+            let above_fluid = chunk_sys.get_cell(vp + IVec3::Y).is_some_and(|c| c.is_fluid());
+            if above_fluid || p.y - p.y.floor() < c.fluid_level() as f32 / Cell::FLUID_LEVELS as f32 {
+                fog.color = cli.underwater_fog_color;
+                fog.falloff = FogFalloff::from_visibility_squared(cli.underwater_fog_visibility);
+            }
+        }
+    }
+
     // Sun Pos
     let sun_angle = worldinfo.daytime * PI * 2.;
 
@@ -453,6 +491,7 @@ pub struct ServerListItem {
 const CLIENT_SETTINGS_FILE: &str = "client.settings.json";
 
 #[derive(Resource, serde::Deserialize, serde::Serialize, Asset, TypePath)]
+#[serde(default)]
 pub struct ClientSettings {
     // Name, Addr
     pub serverlist: Vec<ServerListItem>,
@@ -462,6 +501,8 @@ pub struct ClientSettings {
     pub vsync: bool,
 
     pub chunks_load_distance: IVec2,
+    // chunks farther than this are meshed in lower LOD. see `voxel::chunk_lod`.
+    pub chunks_lod_distance: i32,
 }
 
 impl Default for ClientSettings {
@@ -473,7 +514,8 @@ impl Default for ClientSettings {
             hud_padding: 24.,
             vsync: true,
 
-            chunks_load_distance: IVec2::new(4, 3),
+            chunks_load_distance: IVec2::new(8, 3),
+            chunks_lod_distance: 2,
         }
     }
 }
@@ -505,6 +547,8 @@ pub struct ClientInfo {
     pub sky_fog_is_atomspheric: bool,
     pub skylight_shadow: bool,
     pub skylight_illuminance: f32,
+    pub underwater_fog_color: Color,
+    pub underwater_fog_visibility: f32,
 
     // Control
     pub enable_cursor_look: bool,
@@ -546,6 +590,8 @@ impl Default for ClientInfo {
 
             skylight_shadow: false,
             skylight_illuminance: 20.,
+            underwater_fog_color: Color::rgba(0.05, 0.2, 0.35, 1.0),
+            underwater_fog_visibility: 12.0,
 
             enable_cursor_look: true,
 
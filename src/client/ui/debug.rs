@@ -12,7 +12,6 @@ use bevy_renet::renet::{transport::NetcodeClientTransport, RenetClient};
 use crate::{
     client::{character_controller::CharacterControllerCamera, game_client::{ClientInfo, EthertiaClient, WorldInfo}},
     ui::{color32_of, CurrentUI, UiExtra},
-    util::AsRefMut,
     voxel::{worldgen, Cell, Chunk, ChunkSystem, ClientChunkSystem, HitResult, VoxShape},
 };
 
@@ -163,16 +162,34 @@ pub fn ui_menu_panel(
                                 }
                                 if ui.button("Gen Tree").clicked() {
                                     let p = query_cam.single().translation.as_ivec3();
-                                    let chunk = chunk_sys.get_chunk(Chunk::as_chunkpos(p)).unwrap().as_ref_mut();
-
-                                    worldgen::gen_tree(chunk, Chunk::as_localpos(p), 0.8);
+                                    let outside = {
+                                        let mut chunk = chunk_sys.get_chunk(Chunk::as_chunkpos(p)).unwrap().write().unwrap();
+                                        let mut writer = worldgen::FeatureWriter::new(&mut chunk);
+
+                                        worldgen::gen_tree(&mut writer, Chunk::as_localpos(p), 0.8);
+                                        writer.outside
+                                    };
+                                    // the tree may across the chunk border
+                                    for (chunkpos, cells) in outside {
+                                        let Some(chunkptr) = chunk_sys.get_chunk(chunkpos) else {
+                                            continue;
+                                        };
+                                        {
+                                            let mut chunk = chunkptr.write().unwrap();
+                                            for (local_idx, cell) in cells {
+                                                chunk.set_cell(Chunk::local_idx_pos(local_idx as i32), &cell);
+                                            }
+                                        }
+                                        chunk_sys.mark_chunk_remesh(chunkpos);
+                                    }
+                                    chunk_sys.mark_chunk_remesh(Chunk::as_chunkpos(p));
                                 }
                                 if ui.button("Gen Floor").clicked() {
                                     let campos = query_cam.single().translation.as_ivec3();
 
                                     // crate::util::iter::iter_center_spread(10, 1, |p| {
                                     // });
-                                    let chunk = chunk_sys.get_chunk(Chunk::as_chunkpos(campos)).unwrap().as_ref_mut();
+                                    let mut chunk = chunk_sys.get_chunk(Chunk::as_chunkpos(campos)).unwrap().write().unwrap();
                                     for x in 0..16 {
                                         for z in 0..16 {
                                             chunk.set_cell(IVec3::new(x, 0, z), &Cell::new(1, VoxShape::Cube, 0.));
@@ -306,11 +323,22 @@ RAM: {mem_usage_phys:.2} MB, vir {mem_usage_virtual:.2} MB | {mem_used:.2} / {me
             cam_cell_str = format!("tex: {}, shape: {:?}, isoval: {}", c.tex_id, c.shape_id, c.isovalue());
         }
 
+        let mut biome_str = "none".into();
+        if let Some(worldgen) = &chunk_sys.worldgen {
+            let col = worldgen.column_at(cam_pos.x as i32, cam_pos.z as i32);
+            let climate = worldgen.climate_at(cam_pos.x as i32, cam_pos.z as i32);
+            biome_str = format!(
+                "{:?}, temp: {:.2}, humid: {:.2}, cont: {:.2}, water: {:.1}",
+                col.biome, climate.temperature, climate.humidity, climate.continentalness, col.water_level
+            );
+        }
+
         str_world = format!(
             "
 Cam: ({:.1}, {:.2}, {:.3}). spd: {:.2} mps, {:.2} kph.
 Hit: {hit_str},
 CamCell: {cam_cell_str}
+Biome: {biome_str}
 World: '{}', daytime: {}. inhabited: {}, seed: {}
 Chunk: {} loaded, {num_chunks_loading} loading, {num_chunks_remesh} remesh, {num_chunks_meshing} meshing, -- saving.",
             cam_pos.x,
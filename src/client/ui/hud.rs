@@ -227,7 +227,7 @@ pub fn hud_hotbar(mut ctx: EguiContexts, cli: Res<ClientInfo>, cfg: Res<ClientSe
                     // }
                     // ui.add_sized([s, s], slot);
 
-                    super::ui_item_stack(ui, &ItemStack::new(i as u8, i as u8), &*items);
+                    super::ui_item_stack(ui, &mut ItemStack::new(i as u8, i as u8), &*items);
                 }
             });
         });
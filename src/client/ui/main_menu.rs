@@ -7,7 +7,7 @@ use bevy_egui::{
 use super::{sfx_play, CurrentUI, UiExtra};
 use crate::{client::game_client::EthertiaClient, item::{ItemStack, Items}};
 
-pub fn ui_item_stack(ui: &mut egui::Ui, stack: &ItemStack, reg: &crate::item::Items) {
+pub fn ui_item_stack(ui: &mut egui::Ui, stack: &mut ItemStack, reg: &crate::item::Items) {
 
     let mut slot = egui::Button::new("").fill(Color32::from_black_alpha(100));
 
@@ -33,7 +33,7 @@ pub fn ui_item_stack(ui: &mut egui::Ui, stack: &ItemStack, reg: &crate::item::It
         stack.count.to_string(), egui::FontId::proportional(12.), Color32::from_gray(190));
 
     if resp.clicked() {
-        crate::util::as_mut(stack).count = 0;
+        stack.count = 0;
     }
 }
 
@@ -54,7 +54,7 @@ pub fn ui_main_menu(
             ui.style_mut().spacing.item_spacing = vec2(4., 4.);
 
             for i in 0..30 {
-                ui_item_stack(ui, &ItemStack::new(i, i), &*items);
+                ui_item_stack(ui, &mut ItemStack::new(i, i), &*items);
             }
         });
     });
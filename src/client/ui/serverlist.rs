@@ -2,6 +2,7 @@ use crate::{
     client::prelude::*,
     server::{dedicated_server::rcon::Motd, prelude::ServerSettings},
     util,
+    voxel::WorldGenPresets,
 };
 use bevy::{
     prelude::*,
@@ -323,9 +324,12 @@ pub enum Difficulty {
 
 pub fn ui_create_world(
     mut ctx: EguiContexts,
-    mut cli: ResMut<ClientInfo>,
+    mut cli: EthertiaClient,
+    mut serv_cfg: ResMut<ServerSettings>,
+    presets: Res<WorldGenPresets>,
     mut tx_world_name: Local<String>,
     mut tx_world_seed: Local<String>,
+    mut tx_world_preset: Local<String>,
     mut _difficulty: Local<Difficulty>,
 ) {
     new_egui_window("New World").show(ctx.ctx_mut(), |ui| {
@@ -350,6 +354,19 @@ pub fn ui_create_world(
         sfx_play(ui.text_edit_singleline(&mut *tx_world_seed));
         ui.add_space(space);
 
+        if tx_world_preset.is_empty() {
+            *tx_world_preset = "default".into();
+        }
+        ui.label("World Type:");
+        egui::ComboBox::from_id_source("WorldPreset")
+            .selected_text(tx_world_preset.as_str())
+            .show_ui(ui, |ui| {
+                for name in presets.names() {
+                    sfx_play(ui.selectable_value(&mut *tx_world_preset, name.to_string(), name));
+                }
+            });
+        ui.add_space(space);
+
         ui.label("Gamemode:");
         ui.horizontal(|ui| {
             sfx_play(ui.radio_value(&mut *_difficulty, Difficulty::Peace, "Survival"));
@@ -379,10 +396,18 @@ pub fn ui_create_world(
 
         ui.add_space(22.);
 
-        sfx_play(ui.add_sized([290., 26.], egui::Button::new("Create World").fill(Color32::DARK_GREEN))).clicked();
+        if sfx_play(ui.add_sized([290., 26.], egui::Button::new("Create World").fill(Color32::DARK_GREEN))).clicked() {
+            // the integrated server opens the world when world_dir changed. the seed and preset are only used if the world doesn't exist yet.
+            let name: String = tx_world_name.trim().chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+            serv_cfg.world_dir = format!("saves/{}", if name.is_empty() { "world" } else { &name });
+            serv_cfg.world_seed.clone_from(&tx_world_seed);
+            serv_cfg.world_preset.clone_from(&tx_world_preset);
+
+            cli.connect_server(format!("127.0.0.1:{}", serv_cfg.port));
+        }
         ui.add_space(4.);
         if sfx_play(ui.add_sized([290., 20.], egui::Button::new("Cancel"))).clicked() {
-            cli.curr_ui = CurrentUI::LocalWorldList;
+            cli.data().curr_ui = CurrentUI::LocalWorldList;
         }
         // });
     });
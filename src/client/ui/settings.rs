@@ -7,7 +7,7 @@ use bevy_egui::{
 use crate::{
     client::character_controller::CharacterController,
     client::game_client::{ClientInfo, WorldInfo},
-    voxel::ClientChunkSystem,
+    voxel::{mdl, mtl, Cell, ClientChunkSystem, VoxShape},
 };
 
 use super::{new_egui_window, sfx_play, ui_lr_panel, ClientSettings};
@@ -128,15 +128,41 @@ pub fn ui_settings(
                         ui_setting_line(ui, "Chunk Load Distance X", egui::Slider::new(&mut cfg.chunks_load_distance.x, -1..=25));
                         ui_setting_line(ui, "Chunk Load Distance Y", egui::Slider::new(&mut cfg.chunks_load_distance.y, -1..=25));
 
+                        ui_setting_line(ui, "Chunk LOD Distance", egui::Slider::new(&mut cfg.chunks_lod_distance, 0..=25));
+
                         ui.label("Voxel Brush:");
 
                         ui_setting_line(ui, "Size", egui::Slider::new(&mut vox_brush.size, 0.0..=20.0));
 
                         ui_setting_line(ui, "Indensity", egui::Slider::new(&mut vox_brush.strength, 0.0..=1.0));
 
-                        // ui_setting_line(ui, "Shape", egui::Slider::new(&mut vox_brush.shape, 0..=5));
+                        ui.horizontal(|ui| {
+                            ui.add_space(20.);
+                            ui.colored_label(Color32::WHITE, "Shape");
+                            egui::ComboBox::from_id_source("BrushShape")
+                                .selected_text(format!("{:?}", vox_brush.shape))
+                                .show_ui(ui, |ui| {
+                                    for shape in [
+                                        VoxShape::Isosurface,
+                                        VoxShape::Cube,
+                                        VoxShape::SlabYMin,
+                                        VoxShape::Fence,
+                                        VoxShape::Grass,
+                                        VoxShape::Fluid { level: Cell::FLUID_LEVELS },
+                                    ] {
+                                        ui.selectable_value(&mut vox_brush.shape, shape, format!("{:?}", shape));
+                                    }
+                                    for (mesh_id, path) in mdl::PATHS.iter().enumerate() {
+                                        let shape = VoxShape::CustomMesh {
+                                            mesh_id: mesh_id as u16,
+                                            rotation: 0,
+                                        };
+                                        ui.selectable_value(&mut vox_brush.shape, shape, *path);
+                                    }
+                                });
+                        });
 
-                        ui_setting_line(ui, "Tex", egui::Slider::new(&mut vox_brush.tex, 0..=25));
+                        ui_setting_line(ui, "Tex", egui::Slider::new(&mut vox_brush.tex, 0..=mtl::COUNT - 1));
                     }
                     SettingsPanel::CurrentWorld => {
                         if let Some(worldinfo) = &mut worldinfo {
@@ -78,8 +78,8 @@ fn setup_items(
     asset_server: Res<AssetServer>,
     mut egui_ctx: bevy_egui::EguiContexts,
 ) {
-    let reg = crate::util::as_mut(&items.reg);
-    let items = crate::util::as_mut(&*items);
+    let items = items.as_mut();
+    let reg = &mut items.reg;
     // Food
     items.apple = reg.insert("apple");
      reg.insert("avocado");  // tmp
@@ -1,5 +1,7 @@
 //! Client Networking Handler
 
+use std::sync::Arc;
+
 use bevy::{ecs::system::EntityCommands, prelude::*, utils::HashMap};
 use bevy_renet::{
     renet::{DefaultChannel, DisconnectReason, RenetClient},
@@ -11,11 +13,11 @@ use bevy_xpbd_3d::plugins::collision::Collider;
 use crate::{
     client::prelude::*,
     client::ui::CurrentUI,
-    util::{current_timestamp_millis, AsRefMut},
-    voxel::{Chunk, ChunkSystem, ClientChunkSystem},
+    util::current_timestamp_millis,
+    voxel::{lighting, Chunk, ChunkSystem, ClientChunkSystem, WorldGen},
 };
 
-use super::{packet::CellData, SPacket};
+use super::{packet::CellData, CPacket, RenetClientHelper, SPacket};
 
 pub struct ClientNetworkPlugin;
 
@@ -97,11 +99,18 @@ pub fn client_sys(
                 );
                 // info!("Ping: rtt {}ms = c2s {} + s2c {}", cli.ping.0, cli.ping.1, cli.ping.2);
             }
-            SPacket::LoginSuccess { player_entity } => {
-                info!("Login Success!");
+            SPacket::LoginSuccess {
+                player_entity,
+                world_seed,
+                world_preset,
+            } => {
+                info!("Login Success! World seed: {}", world_seed);
 
                 cli.curr_ui = CurrentUI::None;
 
+                worldinfo.seed = *world_seed;
+                chunk_sys.worldgen = Some(Arc::new(WorldGen::with_preset(*world_seed, world_preset.clone())));
+
                 spawn_player(
                     &mut cmds.get_or_spawn(player_entity.client_entity()), // 为什么在这生成 因为要指定id，以及其他player也是在这生成
                     true,
@@ -111,6 +120,11 @@ pub fn client_sys(
                     &mut materials,
                 );
 
+                // Server will send chunks in the distance, and ChunkModify of these chunks.
+                net_client.send_packet(&CPacket::LoadDistance {
+                    load_distance: cfg.chunks_load_distance,
+                });
+
                 // cmds.insert_resource(WorldInfo::default());  // moved to Click Connect. 要在用之前初始化，如果现在标记 那么就来不及初始化 随后就有ChunkNew数据包 要用到资源
             }
             SPacket::Chat { message } => {
@@ -150,9 +164,21 @@ pub fn client_sys(
                 worldinfo.daytime = *daytime;
             }
             SPacket::ChunkNew { chunkpos, voxel } => {
-                let mut chunk = Chunk::new(*chunkpos);
+                // keep the local chunk if the server's is invalid.
+                if let Err(err) = voxel.validate() {
+                    warn!("Invalid ChunkNew {}: {}", chunkpos, err);
+                    continue;
+                }
 
-                CellData::to_chunk(voxel, &mut chunk);
+                // the chunk may already generated locally. replace it with the server's.
+                if let Some(chunkptr) = chunk_sys.despawn_chunk(*chunkpos) {
+                    if let Some(cmds) = cmds.get_entity(chunkptr.read().unwrap().entity) {
+                        cmds.despawn_recursive();
+                    }
+                }
+
+                let mut chunk = Chunk::new(*chunkpos);
+                chunk.set_cells(voxel.clone());
 
                 chunk_sys.spawn_chunk(chunk, &mut cmds, &mut *meshes);
 
@@ -162,7 +188,7 @@ pub fn client_sys(
                 // info!("ChunkDel: {} ({})", chunkpos, chunk_sys.num_chunks());
 
                 if let Some(chunkptr) = chunk_sys.despawn_chunk(*chunkpos) {
-                    let entity = chunkptr.entity;
+                    let entity = chunkptr.read().unwrap().entity;
 
                     // bug crash: "Attempting to create an EntityCommands for entity 9649v15, which doesn't exist."
                     // why the entity may not exists even if it in the chunk_sys?
@@ -185,10 +211,16 @@ pub fn client_sys(
                     }
                 }
 
-                // todo: NonLock
-                let chunk = chunk_sys.get_chunk(*chunkpos).unwrap();
+                let Some(chunkptr) = chunk_sys.get_chunk(*chunkpos) else {
+                    continue;
+                };
+
+                CellData::to_chunk(voxel, &mut chunkptr.write().unwrap());
 
-                CellData::to_chunk(voxel, chunk.as_ref_mut());
+                let positions: Vec<IVec3> = voxel.iter().map(|c| *chunkpos + Chunk::local_idx_pos(c.local_idx as i32)).collect();
+                for cp in lighting::relight_cells(&*chunk_sys, &positions) {
+                    chunk_sys.mark_chunk_remesh(cp);
+                }
             }
         }
     }
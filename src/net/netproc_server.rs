@@ -1,8 +1,12 @@
 use std::time::Duration;
 
-use bevy::{prelude::*, utils::HashSet};
+use bevy::{
+    prelude::*,
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::{HashMap, HashSet},
+};
 use bevy_renet::{
-    renet::{transport::NetcodeServerTransport, ConnectionConfig, DefaultChannel, RenetServer, ServerEvent},
+    renet::{transport::NetcodeServerTransport, ClientId, ConnectionConfig, DefaultChannel, RenetServer, ServerEvent},
     transport::NetcodeServerPlugin,
     RenetServerPlugin,
 };
@@ -10,8 +14,9 @@ use bevy_renet::{
 use crate::{
     net::{packet::CellData, CPacket, EntityId, RenetServerHelper, SPacket, PROTOCOL_ID},
     server::prelude::*,
-    util::{current_timestamp_millis, AsRefMut},
-    voxel::{ChunkSystem, ServerChunkSystem},
+    util::current_timestamp_millis,
+    voxel::{mdl, mtl, Cell, Chunk, ChunkPtr, ChunkSystem, ServerChunkSystem, VoxShape, WorldGen},
+    wfc::OverlappingModel,
 };
 
 pub struct ServerNetworkPlugin;
@@ -29,6 +34,9 @@ impl Plugin for ServerNetworkPlugin {
         app.add_systems(Startup, bind_server_endpoint);
         app.add_systems(Update, server_sys);
 
+        app.init_resource::<WfcJob>();
+        app.add_systems(Update, wfc_job_complete);
+
         // app.add_systems(Update, ui_server_net);
     }
 }
@@ -45,8 +53,10 @@ pub fn server_sys(
 
     mut serverinfo: ResMut<ServerInfo>,
     // mut worldinfo: ResMut<WorldInfo>,
-    // chunk_sys: ResMut<ServerChunkSystem>,
+    mut chunk_sys: ResMut<ServerChunkSystem>,
     mut cmds: Commands,
+    cfg: Res<ServerSettings>,
+    mut wfc_job: ResMut<WfcJob>,
 ) {
     for event in server_events.read() {
         match event {
@@ -114,7 +124,14 @@ pub fn server_sys(
                     let entity_id = EntityId::from_server(cmds.spawn(TransformBundle::default()).id());
 
                     // Login Success
-                    server.send_packet(client_id, &SPacket::LoginSuccess { player_entity: entity_id });
+                    server.send_packet(
+                        client_id,
+                        &SPacket::LoginSuccess {
+                            player_entity: entity_id,
+                            world_seed: chunk_sys.worldgen.seed,
+                            world_preset: chunk_sys.worldgen.preset.clone(),
+                        },
+                    );
 
                     server.broadcast_packet_chat(format!("Player {} joined. ({}/N)", &username, serverinfo.online_players.len() + 1));
 
@@ -181,6 +198,20 @@ pub fn server_sys(
                                     } else {
                                         server.send_packet_chat(client_id, "Current time is ".into());
                                     }
+                                } else if args[0] == "wfc" {
+                                    if !cfg.is_operator(&player.username) {
+                                        server.send_packet_chat(client_id, "/wfc requires operator".into());
+                                    } else if wfc_job.0.is_some() {
+                                        server.send_packet_chat(client_id, "WFC is busy, wait for the running one".into());
+                                    } else {
+                                        match cmd_wfc(&args[1..], &chunk_sys) {
+                                            Ok((origin, size, task)) => {
+                                                wfc_job.0 = Some((client_id, origin, size, task));
+                                                server.send_packet_chat(client_id, "WFC started".into());
+                                            }
+                                            Err(err) => server.send_packet_chat(client_id, format!("WFC failed: {}", err)),
+                                        }
+                                    }
                                 }
                                 info!("[CMD]: {:?}", args);
                             } else {
@@ -218,14 +249,27 @@ pub fn server_sys(
                             let playerlist = serverinfo.online_players.iter().map(|e| (e.1.username.clone(), e.1.ping_rtt)).collect();
                             server.send_packet(client_id, &SPacket::PlayerList { playerlist });
                         }
-                        // CPacket::ChunkModify { chunkpos, voxel } => {
-                        // todo: NonLock
-                        // let chunk = chunk_sys.get_chunk(chunkpos).unwrap();
+                        CPacket::ChunkModify { chunkpos, voxel } => {
+                            if let Err(err) = check_chunk_modify(player, &chunk_sys, chunkpos, &voxel) {
+                                warn!("Rejected ChunkModify {} from {}: {}", chunkpos, player.username, err);
+                                continue;
+                            }
 
-                        // CellData::to_chunk(&voxel, chunk.as_ref_mut());
+                            let chunkptr = chunk_sys.get_chunk(chunkpos).unwrap();
 
-                        // server.broadcast_packet(&SPacket::ChunkModify { chunkpos, voxel });
-                        // }
+                            CellData::to_chunk(&voxel, &mut chunkptr.write().unwrap());
+                            for c in voxel.iter() {
+                                chunk_sys.notify_changed(chunkpos + Chunk::local_idx_pos(c.local_idx as i32));
+                            }
+
+                            // to every player who has the chunk, and the modifier (may generated the chunk locally)
+                            let packet = SPacket::ChunkModify { chunkpos, voxel };
+                            for player in serverinfo.online_players.values() {
+                                if player.client_id == client_id || player.chunks_loaded.contains(&chunkpos) {
+                                    server.send_packet(player.client_id, &packet);
+                                }
+                            }
+                        }
                         _ => {
                             warn!("Unknown Packet {:?}", packet);
                         }
@@ -235,3 +279,174 @@ pub fn server_sys(
         }
     }
 }
+
+type WfcTask = Task<anyhow::Result<Vec<Cell>>>;
+
+// The running /wfc, one at a time. (requester, origin, size, task of the generated cells)
+#[derive(Resource, Default)]
+struct WfcJob(Option<(ClientId, IVec3, IVec3, WfcTask)>);
+
+// Limits of /wfc. the solve takes about 1s for 4096 cells of a few hundred patterns, in background.
+const WFC_MAX_EXAMPLE_SIZE: i64 = 32;
+const WFC_MAX_SIZE: i32 = 16;
+const WFC_MAX_PATTERNS: usize = 256;
+
+// Chunks of the example region, learned in the task.
+struct ExampleChunks(HashMap<IVec3, ChunkPtr>);
+
+impl ChunkSystem for ExampleChunks {
+    fn get_chunks(&self) -> &HashMap<IVec3, ChunkPtr> {
+        &self.0
+    }
+}
+
+// /wfc <x1 y1 z1> <x2 y2 z2> <x y z> <sx sy sz> [seed]
+// Learn the example region between the 2 corners with the overlapping model, and synthesize a region of the size at x y z.
+// return (origin, size, task of the generated cells), see `wfc_job_complete`.
+fn cmd_wfc(args: &[String], chunk_sys: &ServerChunkSystem) -> anyhow::Result<(IVec3, IVec3, WfcTask)> {
+    if args.len() < 12 {
+        anyhow::bail!("usage: /wfc <x1 y1 z1> <x2 y2 z2> <x y z> <sx sy sz> [seed]");
+    }
+    let nums = args[..12].iter().map(|a| a.parse::<i32>()).collect::<Result<Vec<_>, _>>()?;
+    let vec = |i: usize| IVec3::new(nums[i], nums[i + 1], nums[i + 2]);
+    let (corner1, corner2, origin, size) = (vec(0), vec(3), vec(6), vec(9));
+    if size.min_element() < 1 || size.max_element() > WFC_MAX_SIZE {
+        anyhow::bail!("invalid size {}, 1 to {} per axis", size, WFC_MAX_SIZE);
+    }
+    // the generated cells are at origin..origin+size.
+    if (origin.as_i64vec3() + size.as_i64vec3()).max_element() > i32::MAX as i64 {
+        anyhow::bail!("invalid origin {}", origin);
+    }
+    let example_size = (corner1.as_i64vec3() - corner2.as_i64vec3()).abs() + 1;
+    if example_size.max_element() > WFC_MAX_EXAMPLE_SIZE {
+        anyhow::bail!("the example region {} is too large, max {} per axis", example_size, WFC_MAX_EXAMPLE_SIZE);
+    }
+    let seed = args.get(12).map_or_else(rand::random, |s| WorldGen::parse_seed(s));
+
+    let (min, max) = (corner1.min(corner2), corner1.max(corner2));
+    let mut chunks = HashMap::default();
+    for cy in (Chunk::as_chunkpos(min).y..=max.y).step_by(Chunk::SIZE as usize) {
+        for cz in (Chunk::as_chunkpos(min).z..=max.z).step_by(Chunk::SIZE as usize) {
+            for cx in (Chunk::as_chunkpos(min).x..=max.x).step_by(Chunk::SIZE as usize) {
+                let chunkpos = IVec3::new(cx, cy, cz);
+                if let Some(chunkptr) = chunk_sys.get_chunk(chunkpos) {
+                    chunks.insert(chunkpos, chunkptr.clone());
+                }
+            }
+        }
+    }
+    let example = ExampleChunks(chunks);
+
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let model = OverlappingModel::learn(&example, min, max, 3, true)?;
+        let num_patterns = model.num_patterns();
+        if num_patterns > WFC_MAX_PATTERNS {
+            anyhow::bail!("learned {} patterns, max {}. try a more regular example", num_patterns, WFC_MAX_PATTERNS);
+        }
+        let cells = model.generate(size, seed)?;
+        info!("WFC learned {} patterns, generated {} at {}, seed {}", num_patterns, size, origin, seed);
+        Ok(cells)
+    });
+    Ok((origin, size, task))
+}
+
+// Write the cells generated by /wfc into loaded chunks, and send them to players having the chunks.
+fn wfc_job_complete(
+    mut wfc_job: ResMut<WfcJob>,
+    mut chunk_sys: ResMut<ServerChunkSystem>,
+    mut server: ResMut<RenetServer>,
+    serverinfo: Res<ServerInfo>,
+) {
+    let Some((client_id, origin, size, task)) = &mut wfc_job.0 else {
+        return;
+    };
+    if !task.is_finished() {
+        return;
+    }
+    let (client_id, origin, size) = (*client_id, *origin, *size);
+    let result = futures_lite::future::block_on(futures_lite::future::poll_once(task)).unwrap();
+    wfc_job.0 = None;
+
+    let cells = match result {
+        Ok(cells) => cells,
+        Err(err) => {
+            if serverinfo.online_players.contains_key(&client_id) {
+                server.send_packet_chat(client_id, format!("WFC failed: {}", err));
+            }
+            return;
+        }
+    };
+
+    // only cells in loaded chunks are written.
+    let mut modified: HashMap<IVec3, Vec<CellData>> = HashMap::default();
+    for (i, c) in cells.iter().enumerate() {
+        let i = i as i32;
+        let p = origin + IVec3::new(i % size.x, i / (size.x * size.z), i / size.x % size.z);
+        let chunkpos = Chunk::as_chunkpos(p);
+        if chunk_sys.has_chunk(chunkpos) {
+            let local_idx = Chunk::local_idx(Chunk::as_localpos(p)) as u16;
+            modified.entry(chunkpos).or_default().push(CellData::from_cell(local_idx, c));
+        }
+    }
+
+    let num_chunks = modified.len();
+    for (chunkpos, voxel) in modified {
+        CellData::to_chunk(&voxel, &mut chunk_sys.get_chunk(chunkpos).unwrap().write().unwrap());
+        for c in voxel.iter() {
+            chunk_sys.notify_changed(chunkpos + Chunk::local_idx_pos(c.local_idx as i32));
+        }
+        let packet = SPacket::ChunkModify { chunkpos, voxel };
+        for player in serverinfo.online_players.values() {
+            if player.chunks_loaded.contains(&chunkpos) {
+                server.send_packet(player.client_id, &packet);
+            }
+        }
+    }
+    if serverinfo.online_players.contains_key(&client_id) {
+        server.send_packet_chat(client_id, format!("WFC generated, {} chunks modified", num_chunks));
+    }
+}
+
+// Raycast reach distance (100) + max voxel brush size (20) * sqrt(3)
+const MAX_MODIFY_DISTANCE: f32 = 136.0;
+
+// Validate a ChunkModify from the player before apply it to the ServerChunkSystem.
+fn check_chunk_modify(player: &PlayerInfo, chunk_sys: &ServerChunkSystem, chunkpos: IVec3, voxel: &Vec<CellData>) -> anyhow::Result<()> {
+    if !Chunk::is_chunkpos(chunkpos) {
+        anyhow::bail!("invalid chunkpos");
+    }
+    if !chunk_sys.has_chunk(chunkpos) {
+        anyhow::bail!("chunk not loaded");
+    }
+    if voxel.is_empty() || voxel.len() > Chunk::LOCAL_IDX_CAP {
+        anyhow::bail!("invalid number of cells {}", voxel.len());
+    }
+
+    for c in voxel {
+        if c.local_idx as usize >= Chunk::LOCAL_IDX_CAP {
+            anyhow::bail!("invalid local_idx {}", c.local_idx);
+        }
+        // shape_id is always a valid VoxShape since it's checked when deserialize.
+        if c.tex_id >= mtl::COUNT {
+            anyhow::bail!("invalid tex_id {}", c.tex_id);
+        }
+        match c.shape_id {
+            VoxShape::Fluid { level } if level == 0 || level > Cell::FLUID_LEVELS => {
+                anyhow::bail!("invalid fluid level {}", level);
+            }
+            VoxShape::CustomMesh { mesh_id, .. } if mesh_id >= mdl::COUNT => {
+                anyhow::bail!("invalid mesh_id {}", mesh_id);
+            }
+            VoxShape::CustomMesh { rotation, .. } if rotation > 3 => {
+                anyhow::bail!("invalid rotation {}", rotation);
+            }
+            _ => {}
+        }
+
+        let p = chunkpos + Chunk::local_idx_pos(c.local_idx as i32);
+        if (p.as_vec3() + 0.5).distance(player.position) > MAX_MODIFY_DISTANCE {
+            anyhow::bail!("out of reach {}", p);
+        }
+    }
+    Ok(())
+}
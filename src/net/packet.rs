@@ -1,7 +1,7 @@
 use bevy::math::{IVec2, IVec3, Vec3};
 use serde::{Deserialize, Serialize};
 
-use crate::voxel::{Cell, Chunk, VoxShape};
+use crate::voxel::{Cell, Chunk, PalettedCells, VoxShape, WorldGenPreset};
 
 use super::EntityId;
 
@@ -24,18 +24,6 @@ impl CellData {
         }
     }
 
-    pub fn from_chunk(chunk: &Chunk) -> Vec<CellData> {
-        let mut data = Vec::new();
-        for i in 0..Chunk::LOCAL_IDX_CAP {
-            let c = chunk.get_cell(Chunk::local_idx_pos(i as i32));
-            if !c.is_tex_empty() {
-                // FIXED: Dont use {isovalue() > -0.5} as condition, because Non-Isosurface voxels e.g. Leaves should always be transmit regardless it's isovalue
-                // dens: ((c.value + 0.5).clamp(0.0, 1.0) * 255.0) as u8
-                data.push(CellData::from_cell(i as u16, c));
-            }
-        }
-        data
-    }
     pub fn to_chunk(data: &Vec<CellData>, chunk: &mut Chunk) {
         for c in data {
             let mut a = Cell::new(c.tex_id, c.shape_id, 0.0);
@@ -87,6 +75,9 @@ pub enum SPacket {
     LoginSuccess {
         // uuid, username
         player_entity: EntityId,
+        // clients generate chunks locally with the same seed and preset, before the server's arrives.
+        world_seed: u64,
+        world_preset: WorldGenPreset,
     },
 
     // Play
@@ -114,7 +105,7 @@ pub enum SPacket {
 
     ChunkNew {
         chunkpos: IVec3,
-        voxel: Vec<CellData>, // or use full-chunk fixed array?
+        voxel: PalettedCells,
     },
     ChunkDel {
         chunkpos: IVec3,
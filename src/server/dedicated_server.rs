@@ -90,10 +90,39 @@ pub mod rcon {
 }
 
 #[derive(Resource, serde::Deserialize, serde::Serialize, Asset, TypePath, Clone)]
+#[serde(default)]
 pub struct ServerSettings {
     pub port: u16,
     pub num_player_limit: u32,
     pub motd: String,
+
+    // directory of the world save. region files, etc.
+    pub world_dir: String,
+
+    // seed of a newly created world. integers are used as they are, other text is hashed, empty for random.
+    // ignored if the world already exists, its seed is stored in the world meta.
+    pub world_seed: String,
+
+    // worldgen preset of a newly created world, name of a file in `assets/worldgen`. ignored if the world already exists.
+    pub world_preset: String,
+
+    // seconds between saving modified chunks. 0 to disable autosave (still saves on chunk unload and exit).
+    pub autosave_interval: f32,
+
+    // seconds between steps of the fluid simulation. 0 to disable flowing.
+    pub fluid_tick_interval: f32,
+
+    // max number of cells updated by a fluid step, the rest are updated in the next steps.
+    pub fluid_tick_budget: usize,
+
+    // seconds between block ticks, e.g. falling sand. 0 to disable.
+    pub block_tick_interval: f32,
+
+    // number of random cells ticked per loaded chunk per block tick, e.g. grass spreading.
+    pub random_ticks_per_chunk: u32,
+
+    // usernames allowed to run operator commands, e.g. /wfc. "*" for everyone.
+    pub operators: Vec<String>,
 }
 
 impl Default for ServerSettings {
@@ -102,10 +131,25 @@ impl Default for ServerSettings {
             port: 4060,
             num_player_limit: 80, 
             motd: "An Ethertum Server".into(),
+            world_dir: "saves/world".into(),
+            world_seed: String::new(),
+            world_preset: "default".into(),
+            autosave_interval: 60.0,
+            fluid_tick_interval: 0.25,
+            fluid_tick_budget: 4096,
+            block_tick_interval: 0.05,
+            random_ticks_per_chunk: 3,
+            operators: Vec::new(),
         }
     }
 }
 
+impl ServerSettings {
+    pub fn is_operator(&self, username: &str) -> bool {
+        self.operators.iter().any(|op| op == "*" || op == username)
+    }
+}
+
 #[derive(Resource, Default)]
 pub struct ServerInfo {
     // PlayerList
@@ -11,6 +11,8 @@ impl Plugin for IntegratedServerPlugin {
         app.insert_resource(ServerInfo::default());
         app.insert_resource(ServerSettings {
             port: 6000 + rand::thread_rng().gen_range(0..6000),
+            // the local player hosting the world.
+            operators: vec!["*".into()],
             ..default()
         });
 
@@ -1,36 +1,8 @@
 #[macro_use]
 mod macros;
 
-#[allow(invalid_reference_casting)]
-pub fn as_mut<T>(v: &T) -> &mut T {
-    unsafe { &mut *((v as *const T) as *mut T) }
-}
-
-pub trait AsRefMut<T> {
-    fn as_ref_mut(&self) -> &mut T;
-}
-
-impl AsRefMut<crate::voxel::Chunk> for Arc<crate::voxel::Chunk> {
-    fn as_ref_mut(&self) -> &mut crate::voxel::Chunk {
-        as_mut(self.as_ref())
-    }
-}
-
-impl AsRefMut<crate::voxel::Cell> for crate::voxel::Cell {
-    fn as_ref_mut(&self) -> &mut crate::voxel::Cell {
-        as_mut(self)
-    }
-}
-
-// impl<T, U> AsRefMut<U> for T where T: AsRef<U> {
-//     fn as_ref_mut(&self) -> &mut U {
-//         as_mut(self.as_ref())
-//     }
-// }
-
 pub mod registry;
 
-use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
 pub mod iter {
@@ -96,6 +68,10 @@ pub fn hash(i: i32) -> f32 {
 pub fn hash3(v: IVec3) -> Vec3 {
     Vec3::new(hash(v.x), hash(v.y), hash(v.z))
 }
+/// Stable 64-bit hash of a position. for seeding per-chunk rngs, unlike `hashcode` it's the same across platforms and versions.
+pub fn hash_ivec3(v: IVec3) -> u64 {
+    (v.x as i64 as u64).wrapping_mul(0x9E3779B97F4A7C15) ^ (v.y as i64 as u64).wrapping_mul(0xC2B2AE3D27D4EB4F) ^ (v.z as i64 as u64).wrapping_mul(0x165667B19E3779F9)
+}
 
 pub fn current_timestamp() -> Duration {
     SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap()
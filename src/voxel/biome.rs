@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::material::mtl;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, Reflect)]
+pub enum Biome {
+    Ocean,
+    Beach,
+    Plains,
+    Forest,
+    Desert,
+    Savanna,
+    Swamp,
+    Taiga,
+    Tundra,
+    Mountains,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TreeKind {
+    Oak,
+    Spruce,
+    Acacia,
+}
+
+/// Climate of a world column. each in [-1, 1].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Climate {
+    pub temperature: f32,
+    pub humidity: f32,
+    // -1: deep ocean, 0: coast, 1: inland mountains
+    pub continentalness: f32,
+}
+
+pub struct BiomeDef {
+    pub biome: Biome,
+
+    // the typical climate of the biome. biomes are weighted by the distance to it.
+    pub climate: Climate,
+
+    // Terrain Shape. height offset and amplitude of the heightfield noise, in 18-blocks units.
+    pub height_offset: f32,
+    pub height_scale: f32,
+    pub water_level: f32,
+
+    pub surface: u16,
+    pub subsurface: u16,
+
+    // grass is placed where the grass noise > threshold. 1.0 for no grass.
+    pub grass_threshold: f32,
+    // foliage of grass noise bands: (>0.94, >0.8, >0.24, else)
+    pub foliage: [u16; 4],
+
+    pub tree: Option<TreeKind>,
+    // chance of a tree per column, of 256.
+    pub tree_chance: f32,
+}
+
+const fn climate(temperature: f32, humidity: f32, continentalness: f32) -> Climate {
+    Climate {
+        temperature,
+        humidity,
+        continentalness,
+    }
+}
+
+const FOLIAGE_DEFAULT: [u16; 4] = [mtl::ROSE, mtl::FERN, mtl::BUSH, mtl::SHORTGRASS];
+
+pub static BIOMES: [BiomeDef; 10] = [
+    BiomeDef {
+        biome: Biome::Ocean,
+        climate: climate(0.0, 0.0, -0.6),
+        height_offset: -1.2,
+        height_scale: 0.6,
+        water_level: 0.0,
+        surface: mtl::SAND,
+        subsurface: mtl::GRAVEL,
+        grass_threshold: 1.0,
+        foliage: FOLIAGE_DEFAULT,
+        tree: None,
+        tree_chance: 0.0,
+    },
+    BiomeDef {
+        biome: Biome::Beach,
+        climate: climate(0.2, 0.0, -0.2),
+        height_offset: -0.1,
+        height_scale: 0.3,
+        water_level: 0.0,
+        surface: mtl::SAND,
+        subsurface: mtl::SAND,
+        grass_threshold: 1.0,
+        foliage: FOLIAGE_DEFAULT,
+        tree: None,
+        tree_chance: 0.0,
+    },
+    BiomeDef {
+        biome: Biome::Plains,
+        climate: climate(0.1, -0.1, 0.2),
+        height_offset: 0.0,
+        height_scale: 1.0,
+        water_level: 0.0,
+        surface: mtl::GRASS,
+        subsurface: mtl::DIRT,
+        grass_threshold: 0.0,
+        foliage: FOLIAGE_DEFAULT,
+        tree: Some(TreeKind::Oak),
+        tree_chance: 1.0,
+    },
+    BiomeDef {
+        biome: Biome::Forest,
+        climate: climate(0.0, 0.4, 0.3),
+        height_offset: 0.1,
+        height_scale: 1.0,
+        water_level: 0.0,
+        surface: mtl::GRASS,
+        subsurface: mtl::DIRT,
+        grass_threshold: 0.1,
+        foliage: [mtl::FERN, mtl::FERN, mtl::BUSH, mtl::SHORTGRASS],
+        tree: Some(TreeKind::Oak),
+        tree_chance: 6.0,
+    },
+    BiomeDef {
+        biome: Biome::Desert,
+        climate: climate(0.8, -0.7, 0.3),
+        height_offset: 0.1,
+        height_scale: 0.7,
+        water_level: -8.0,
+        surface: mtl::SAND,
+        subsurface: mtl::SAND,
+        grass_threshold: 0.9,
+        foliage: [mtl::BUSH, mtl::BUSH, mtl::BUSH, mtl::BUSH],
+        tree: None,
+        tree_chance: 0.0,
+    },
+    BiomeDef {
+        biome: Biome::Savanna,
+        climate: climate(0.6, -0.2, 0.3),
+        height_offset: 0.0,
+        height_scale: 0.8,
+        water_level: 0.0,
+        surface: mtl::DRY_GRASS,
+        subsurface: mtl::DIRT,
+        grass_threshold: 0.2,
+        foliage: [mtl::BUSH, mtl::SHORTGRASS, mtl::SHORTGRASS, mtl::SHORTGRASS],
+        tree: Some(TreeKind::Acacia),
+        tree_chance: 1.0,
+    },
+    BiomeDef {
+        biome: Biome::Swamp,
+        climate: climate(0.3, 0.8, 0.0),
+        height_offset: -0.05,
+        height_scale: 0.4,
+        water_level: 1.0,
+        surface: mtl::MOSS,
+        subsurface: mtl::DIRT,
+        grass_threshold: -0.2,
+        foliage: [mtl::FERN, mtl::FERN, mtl::BUSH, mtl::SHORTGRASS],
+        tree: Some(TreeKind::Oak),
+        tree_chance: 3.0,
+    },
+    BiomeDef {
+        biome: Biome::Taiga,
+        climate: climate(-0.5, 0.3, 0.4),
+        height_offset: 0.2,
+        height_scale: 1.2,
+        water_level: 0.0,
+        surface: mtl::GRASS,
+        subsurface: mtl::DIRT,
+        grass_threshold: 0.3,
+        foliage: [mtl::FERN, mtl::FERN, mtl::SHORTGRASS, mtl::FERN],
+        tree: Some(TreeKind::Spruce),
+        tree_chance: 5.0,
+    },
+    BiomeDef {
+        biome: Biome::Tundra,
+        climate: climate(-0.8, -0.3, 0.3),
+        height_offset: 0.1,
+        height_scale: 0.6,
+        water_level: 0.0,
+        surface: mtl::SNOW,
+        subsurface: mtl::DIRT,
+        grass_threshold: 1.0,
+        foliage: FOLIAGE_DEFAULT,
+        tree: Some(TreeKind::Spruce),
+        tree_chance: 0.3,
+    },
+    BiomeDef {
+        biome: Biome::Mountains,
+        climate: climate(-0.2, 0.0, 1.2),
+        height_offset: 1.0,
+        height_scale: 2.5,
+        water_level: 0.0,
+        surface: mtl::STONE,
+        subsurface: mtl::STONE,
+        grass_threshold: 1.0,
+        foliage: FOLIAGE_DEFAULT,
+        tree: None,
+        tree_chance: 0.0,
+    },
+];
+
+impl Biome {
+    pub fn def(self) -> &'static BiomeDef {
+        &BIOMES[self as usize]
+    }
+}
+
+// width of biome borders in climate space. bigger is smoother (and less distinct).
+const BLEND_SIGMA: f32 = 0.25;
+
+/// Normalized weights of all biomes (indexed as `BIOMES`) for the climate.
+/// weights fall off smoothly with the climate distance, so biome properties blend continuously at borders.
+pub fn biome_weights(c: &Climate) -> [f32; BIOMES.len()] {
+    let mut dists = [0.0; BIOMES.len()];
+    for (i, def) in BIOMES.iter().enumerate() {
+        let d = Vec3::new(
+            c.temperature - def.climate.temperature,
+            c.humidity - def.climate.humidity,
+            // continentalness dominates, oceans/mountains shouldn't appear at wrong heights.
+            (c.continentalness - def.climate.continentalness) * 2.0,
+        );
+        dists[i] = d.length_squared();
+    }
+
+    // relative to the nearest biome, avoids all weights underflow to 0 in extreme climates.
+    let min_dist = dists.iter().cloned().fold(f32::MAX, f32::min);
+    let mut weights = dists.map(|d| (-(d - min_dist) / (BLEND_SIGMA * BLEND_SIGMA)).exp());
+    let sum: f32 = weights.iter().sum();
+    weights.iter_mut().for_each(|w| *w /= sum);
+    weights
+}
@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use bevy::{
+    math::ivec3,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{mtl, Cell, Chunk, ChunkPtr, VoxShape};
+
+// Cells changing over time, on the server. behaviors of materials are called by:
+//   block updates, when the cell or a neighbor changed. e.g. sand starts falling when the cell below is dug.
+//   scheduled ticks, some ticks after requested. e.g. the delay of falling.
+//   random ticks, a few random cells of each loaded chunk per tick. for slow changes, e.g. grass spreading.
+// Cells changed by behaviors trigger block updates in the next tick.
+
+const NEIGHBORS: [IVec3; 6] = [
+    ivec3(1, 0, 0),
+    ivec3(-1, 0, 0),
+    ivec3(0, 1, 0),
+    ivec3(0, -1, 0),
+    ivec3(0, 0, 1),
+    ivec3(0, 0, -1),
+];
+
+/// Behavior of the cells of a material, registered by tex_id in `BlockBehaviors`.
+pub trait BlockBehavior: Send + Sync {
+    /// The cell or a neighbor changed.
+    fn on_block_update(&self, _world: &mut BlockWorld, _p: IVec3, _c: Cell) {}
+
+    /// Requested by `BlockWorld::schedule_tick`.
+    fn on_scheduled_tick(&self, _world: &mut BlockWorld, _p: IVec3, _c: Cell) {}
+
+    /// The cell is randomly picked.
+    fn on_random_tick(&self, _world: &mut BlockWorld, _p: IVec3, _c: Cell) {}
+}
+
+#[derive(Default)]
+pub struct BlockBehaviors {
+    behaviors: HashMap<u16, Box<dyn BlockBehavior>>,
+}
+
+impl BlockBehaviors {
+    /// Grass spreading, leaves decay, falling sand and gravel.
+    pub fn with_defaults() -> Self {
+        let mut behaviors = Self::default();
+        behaviors.register(mtl::GRASS, GrassSpread);
+        behaviors.register(mtl::LEAVES, LeavesDecay);
+        behaviors.register(mtl::SAND, Falling);
+        behaviors.register(mtl::GRAVEL, Falling);
+        behaviors
+    }
+
+    pub fn register(&mut self, tex_id: u16, behavior: impl BlockBehavior + 'static) {
+        self.behaviors.insert(tex_id, Box::new(behavior));
+    }
+
+    /// Foliage cells have none, their tex_id are of the foliage atlas. e.g. mtl::SHORTGRASS == mtl::LOG.
+    pub fn get(&self, c: &Cell) -> Option<&dyn BlockBehavior> {
+        if c.shape_id == VoxShape::Grass {
+            return None;
+        }
+        self.behaviors.get(&c.tex_id).map(|b| b.as_ref())
+    }
+
+    fn get_at(&self, world: &BlockWorld, p: IVec3) -> Option<(Cell, &dyn BlockBehavior)> {
+        let c = world.get_cell(p)?;
+        Some((c, self.get(&c)?))
+    }
+}
+
+/// Block updates and scheduled ticks waiting, of the loaded chunks.
+pub struct BlockUpdates {
+    tick: u64,
+    block_updates: HashSet<IVec3>,
+    scheduled: BTreeMap<u64, Vec<IVec3>>,
+    rng: StdRng,
+}
+
+impl BlockUpdates {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            tick: 0,
+            block_updates: HashSet::default(),
+            scheduled: BTreeMap::new(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Block updates of the changed cell and its 6 neighbors, in the next tick.
+    pub fn notify_changed(&mut self, p: IVec3) {
+        self.block_updates.insert(p);
+        for d in NEIGHBORS {
+            self.block_updates.insert(p + d);
+        }
+    }
+
+    /// Scheduled tick of the cell after `delay` ticks, at least 1.
+    pub fn schedule_tick(&mut self, p: IVec3, delay: u64) {
+        self.scheduled.entry(self.tick + delay.max(1)).or_default().push(p);
+    }
+
+    pub fn clear(&mut self) {
+        self.block_updates.clear();
+        self.scheduled.clear();
+    }
+
+    /// Run block updates, due scheduled ticks, and `random_ticks_per_chunk` random ticks of each loaded chunk.
+    /// cells of the unloaded chunks are dropped. return the changed cells of each chunk.
+    pub fn tick(
+        &mut self,
+        chunks: &HashMap<IVec3, ChunkPtr>,
+        behaviors: &BlockBehaviors,
+        random_ticks_per_chunk: u32,
+    ) -> Vec<(IVec3, Vec<(u16, Cell)>)> {
+        self.tick += 1;
+        let mut block_updates: Vec<IVec3> = std::mem::take(&mut self.block_updates).into_iter().collect();
+        block_updates.sort_unstable_by_key(|p| (p.y, p.z, p.x));
+        let mut scheduled = self.scheduled.remove(&self.tick).unwrap_or_default();
+        scheduled.sort_unstable_by_key(|p| (p.y, p.z, p.x));
+        scheduled.dedup();
+
+        let mut world = BlockWorld {
+            chunks,
+            updates: self,
+            modified: HashMap::default(),
+        };
+        for p in block_updates {
+            if let Some((c, behavior)) = behaviors.get_at(&world, p) {
+                behavior.on_block_update(&mut world, p, c);
+            }
+        }
+        for p in scheduled {
+            if let Some((c, behavior)) = behaviors.get_at(&world, p) {
+                behavior.on_scheduled_tick(&mut world, p, c);
+            }
+        }
+        for chunkpos in chunks.keys() {
+            for _ in 0..random_ticks_per_chunk {
+                let p = *chunkpos + Chunk::local_idx_pos(world.rng().gen_range(0..Chunk::LOCAL_IDX_CAP as i32));
+                if let Some((c, behavior)) = behaviors.get_at(&world, p) {
+                    behavior.on_random_tick(&mut world, p, c);
+                }
+            }
+        }
+
+        let mut modified: Vec<_> = world.modified.into_iter().collect();
+        modified.sort_unstable_by_key(|(cp, _)| (cp.y, cp.z, cp.x));
+        modified
+    }
+}
+
+/// The loaded chunks, accessed by behaviors.
+pub struct BlockWorld<'a> {
+    chunks: &'a HashMap<IVec3, ChunkPtr>,
+    updates: &'a mut BlockUpdates,
+    modified: HashMap<IVec3, Vec<(u16, Cell)>>,
+}
+
+impl BlockWorld<'_> {
+    /// None if the chunk is not loaded.
+    pub fn get_cell(&self, p: IVec3) -> Option<Cell> {
+        let chunkptr = self.chunks.get(&Chunk::as_chunkpos(p))?;
+        Some(*chunkptr.read().unwrap().get_cell(Chunk::as_localpos(p)))
+    }
+
+    /// Set the cell of a loaded chunk, and update it and its neighbors in the next tick.
+    pub fn set_cell(&mut self, p: IVec3, c: &Cell) -> bool {
+        let (chunkpos, lp) = (Chunk::as_chunkpos(p), Chunk::as_localpos(p));
+        let Some(chunkptr) = self.chunks.get(&chunkpos) else {
+            return false;
+        };
+        chunkptr.write().unwrap().set_cell(lp, c);
+        self.modified.entry(chunkpos).or_default().push((Chunk::local_idx(lp) as u16, *c));
+        self.updates.notify_changed(p);
+        true
+    }
+
+    pub fn schedule_tick(&mut self, p: IVec3, delay: u64) {
+        self.updates.schedule_tick(p, delay);
+    }
+
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.updates.rng
+    }
+}
+
+// solid terrain or opaque cubes.
+fn is_solid(c: &Cell) -> bool {
+    c.is_obaque_cube() || !c.is_isoval_empty()
+}
+
+// not under a solid or a fluid.
+fn is_uncovered(world: &BlockWorld, p: IVec3) -> bool {
+    world.get_cell(p + IVec3::Y).is_some_and(|a| !is_solid(&a) && !a.is_fluid())
+}
+
+/// Grass spreads onto the uncovered dirt nearby, on random ticks.
+pub struct GrassSpread;
+
+impl BlockBehavior for GrassSpread {
+    fn on_random_tick(&self, world: &mut BlockWorld, p: IVec3, _c: Cell) {
+        if !is_uncovered(world, p) {
+            return;
+        }
+        let rng = world.rng();
+        let q = p + ivec3(rng.gen_range(-1..=1), rng.gen_range(-3..=1), rng.gen_range(-1..=1));
+        let Some(mut dirt) = world.get_cell(q).filter(|c| c.tex_id == mtl::DIRT && c.shape_id != VoxShape::Grass) else {
+            return;
+        };
+        if is_uncovered(world, q) {
+            dirt.tex_id = mtl::GRASS;
+            world.set_cell(q, &dirt);
+        }
+    }
+}
+
+/// Leaves decay some time after they lost the support: a LOG reachable through leaves, or a solid cell above
+/// which the leaves hang from, e.g. vines.
+pub struct LeavesDecay;
+
+impl LeavesDecay {
+    /// Max number of leaves between the supported leaves and the support.
+    pub const DISTANCE: i32 = 12;
+
+    fn is_log(c: &Cell) -> bool {
+        c.tex_id == mtl::LOG && c.shape_id != VoxShape::Grass
+    }
+
+    fn is_leaves(c: &Cell) -> bool {
+        c.tex_id == mtl::LEAVES && c.shape_id == VoxShape::Leaves
+    }
+
+    // Breadth-first through the leaves. unloaded chunks are assumed to support.
+    fn is_supported(world: &BlockWorld, p: IVec3) -> bool {
+        let mut visited = HashSet::default();
+        let mut queue = VecDeque::from([(p, 0)]);
+        visited.insert(p);
+        while let Some((p, dist)) = queue.pop_front() {
+            for d in NEIGHBORS {
+                let Some(n) = world.get_cell(p + d) else {
+                    return true;
+                };
+                if Self::is_log(&n) || (d == IVec3::Y && is_solid(&n)) {
+                    return true;
+                }
+                if dist < Self::DISTANCE && Self::is_leaves(&n) && visited.insert(p + d) {
+                    queue.push_back((p + d, dist + 1));
+                }
+            }
+        }
+        false
+    }
+}
+
+impl BlockBehavior for LeavesDecay {
+    fn on_block_update(&self, world: &mut BlockWorld, p: IVec3, _c: Cell) {
+        // spread over time, not the whole tree at once.
+        let delay = world.rng().gen_range(20..60);
+        world.schedule_tick(p, delay);
+    }
+
+    fn on_scheduled_tick(&self, world: &mut BlockWorld, p: IVec3, c: Cell) {
+        if !Self::is_leaves(&c) || Self::is_supported(world, p) {
+            return;
+        }
+        let mut air = c;
+        air.tex_id = mtl::NIL;
+        air.shape_id = VoxShape::Isosurface;
+        world.set_cell(p, &air);
+    }
+}
+
+/// Falls down into the empty or fluid cell below, swapping with it.
+pub struct Falling;
+
+impl Falling {
+    /// Ticks per cell.
+    pub const DELAY: u64 = 2;
+
+    fn can_fall_into(c: &Cell) -> bool {
+        c.is_fluid() || (c.is_tex_empty() && c.is_isoval_empty())
+    }
+}
+
+impl BlockBehavior for Falling {
+    fn on_block_update(&self, world: &mut BlockWorld, p: IVec3, _c: Cell) {
+        if world.get_cell(p - IVec3::Y).is_some_and(|b| Self::can_fall_into(&b)) {
+            world.schedule_tick(p, Self::DELAY);
+        }
+    }
+
+    fn on_scheduled_tick(&self, world: &mut BlockWorld, p: IVec3, c: Cell) {
+        let Some(below) = world.get_cell(p - IVec3::Y).filter(Self::can_fall_into) else {
+            return;
+        };
+        world.set_cell(p, &below);
+        world.set_cell(p - IVec3::Y, &c);
+        // keeps falling a cell per DELAY, instead of waiting for the block update of the change.
+        if world.get_cell(p - IVec3::Y * 2).is_some_and(|b| Self::can_fall_into(&b)) {
+            world.schedule_tick(p - IVec3::Y, Self::DELAY);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+    use crate::util::iter;
+
+    fn air() -> Cell {
+        Cell::new(mtl::NIL, VoxShape::Isosurface, -0.5)
+    }
+
+    fn solid(tex_id: u16) -> Cell {
+        Cell::new(tex_id, VoxShape::Isosurface, 0.5)
+    }
+
+    fn leaves() -> Cell {
+        Cell::new(mtl::LEAVES, VoxShape::Leaves, 0.0)
+    }
+
+    // 2x1x1 chunks at y=0, dirt below y=4, air above.
+    fn new_world() -> HashMap<IVec3, ChunkPtr> {
+        let mut chunks = HashMap::default();
+        for x in [0, 1] {
+            let chunkpos = IVec3::new(x, 0, 0) * Chunk::SIZE;
+            let mut chunk = Chunk::new(chunkpos);
+            iter::iter_xzy(Chunk::SIZE, |lp| chunk.set_cell(lp, &if lp.y < 4 { solid(mtl::DIRT) } else { air() }));
+            chunks.insert(chunkpos, Arc::new(RwLock::new(chunk)));
+        }
+        chunks
+    }
+
+    fn get(chunks: &HashMap<IVec3, ChunkPtr>, p: IVec3) -> Cell {
+        *chunks[&Chunk::as_chunkpos(p)].read().unwrap().get_cell(Chunk::as_localpos(p))
+    }
+
+    fn set(chunks: &HashMap<IVec3, ChunkPtr>, p: IVec3, c: &Cell) {
+        chunks[&Chunk::as_chunkpos(p)].write().unwrap().set_cell(Chunk::as_localpos(p), c);
+    }
+
+    #[test]
+    fn sand_falls_until_landed() {
+        let chunks = new_world();
+        let behaviors = BlockBehaviors::with_defaults();
+        let mut updates = BlockUpdates::new(1);
+        let p = IVec3::new(4, 10, 4);
+        set(&chunks, p, &solid(mtl::SAND));
+        updates.notify_changed(p);
+
+        // starts falling on the block update, then a cell per DELAY ticks, down to the floor at y=4.
+        let sand_y = |chunks: &HashMap<IVec3, ChunkPtr>| (0..Chunk::SIZE).find(|y| get(chunks, IVec3::new(4, *y, 4)).tex_id == mtl::SAND);
+        updates.tick(&chunks, &behaviors, 0);
+        for fallen in 1..=6 {
+            for _ in 0..Falling::DELAY {
+                assert_eq!(sand_y(&chunks), Some(p.y - fallen + 1));
+                updates.tick(&chunks, &behaviors, 0);
+            }
+            assert_eq!(sand_y(&chunks), Some(p.y - fallen), "after {} cells", fallen);
+        }
+        for _ in 0..Falling::DELAY * 4 {
+            assert!(updates.tick(&chunks, &behaviors, 0).is_empty());
+        }
+        assert_eq!(sand_y(&chunks), Some(4));
+        assert!(get(&chunks, p).is_tex_empty());
+    }
+
+    #[test]
+    fn leaves_decay_beyond_distance() {
+        let chunks = new_world();
+        let behaviors = BlockBehaviors::with_defaults();
+        let mut updates = BlockUpdates::new(1);
+        // a log with a row of leaves, longer than the support distance.
+        let log = IVec3::new(2, 8, 8);
+        for y in 4..=log.y {
+            set(&chunks, IVec3::new(log.x, y, log.z), &solid(mtl::LOG));
+        }
+        let row = LeavesDecay::DISTANCE + 4;
+        for i in 1..=row {
+            set(&chunks, log + IVec3::X * i, &leaves());
+        }
+        // a vine hanging from stone.
+        let overhang = IVec3::new(20, 12, 2);
+        set(&chunks, overhang, &solid(mtl::STONE));
+        for y in 6..overhang.y {
+            set(&chunks, IVec3::new(overhang.x, y, overhang.z), &leaves());
+        }
+        for i in 1..=row {
+            updates.notify_changed(log + IVec3::X * i);
+        }
+        updates.notify_changed(IVec3::new(overhang.x, 6, overhang.z));
+
+        for _ in 0..200 {
+            updates.tick(&chunks, &behaviors, 0);
+        }
+        // leaves are supported through up to DISTANCE leaves.
+        for i in 1..=LeavesDecay::DISTANCE + 1 {
+            assert!(LeavesDecay::is_leaves(&get(&chunks, log + IVec3::X * i)), "decayed {} from the log", i);
+        }
+        for i in LeavesDecay::DISTANCE + 2..=row {
+            assert!(get(&chunks, log + IVec3::X * i).is_tex_empty(), "not decayed {} from the log", i);
+        }
+        for y in 6..overhang.y {
+            assert!(LeavesDecay::is_leaves(&get(&chunks, IVec3::new(overhang.x, y, overhang.z))));
+        }
+    }
+
+    #[test]
+    fn grass_spreads_onto_uncovered_dirt() {
+        let chunks = new_world();
+        let behaviors = BlockBehaviors::with_defaults();
+        let mut updates = BlockUpdates::new(1);
+        set(&chunks, IVec3::new(8, 3, 8), &solid(mtl::GRASS));
+        // foliage of the same tex_id as dirt, doesn't turn into grass. the dirt under it does.
+        let foliage = Cell::new(mtl::DIRT, VoxShape::Grass, 0.0);
+        for x in 4..12 {
+            set(&chunks, IVec3::new(x, 4, 4), &foliage);
+        }
+        // covered dirt, and sand.
+        for x in 0..Chunk::SIZE {
+            set(&chunks, IVec3::new(x, 4, 12), &solid(mtl::STONE));
+            set(&chunks, IVec3::new(x, 3, 14), &solid(mtl::SAND));
+        }
+
+        for _ in 0..20000 {
+            updates.tick(&chunks, &behaviors, 64);
+        }
+        for x in 4..12 {
+            assert_eq!(get(&chunks, IVec3::new(x, 4, 4)), foliage);
+            assert_eq!(get(&chunks, IVec3::new(x, 3, 4)).tex_id, mtl::GRASS);
+        }
+        for x in 0..Chunk::SIZE {
+            assert_eq!(get(&chunks, IVec3::new(x, 3, 12)).tex_id, mtl::DIRT);
+            assert_eq!(get(&chunks, IVec3::new(x, 3, 14)).tex_id, mtl::SAND);
+            // below the surface.
+            assert_eq!(get(&chunks, IVec3::new(x, 2, 8)).tex_id, mtl::DIRT);
+        }
+        assert_eq!(get(&chunks, IVec3::new(8, 3, 10)).tex_id, mtl::GRASS);
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    f32::consts::{PI, TAU},
+    sync::{Arc, Mutex},
+};
+
+use bevy::{math::DVec3, prelude::*, utils::HashMap};
+use noise::{NoiseFn, Perlin};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use super::Chunk;
+use crate::util::hash_ivec3;
+
+/// Parameters of the cave carvers. lengths and radii are in blocks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaveConfig {
+    pub enabled: bool,
+
+    // caves are only carved in this height range.
+    pub min_y: i32,
+    pub max_y: i32,
+    // below water level, only cells deeper than this terrain isovalue are carved.
+    // keeps a solid shell under oceans and lakes so they don't drain into caves. (terrain isovalue is ~0.055 per block)
+    pub water_guard: f32,
+
+    // Cheese: large caverns where the 3D noise exceeds the threshold.
+    pub cheese_scale: f32,
+    pub cheese_threshold: f32,
+
+    // Spaghetti: long thin tunnels along the intersection of the zero-surfaces of 2 noises.
+    pub spaghetti_scale: f32,
+    pub spaghetti_width: f32,
+
+    // Worms: random walk tunnels. each chunk starts one by the chance.
+    pub worm_chance: f32,
+    pub worm_length: u32,
+    pub worm_radius: [f32; 2],
+
+    // Ravines: narrow, tall and mostly horizontal walks.
+    pub ravine_chance: f32,
+    pub ravine_length: u32,
+    pub ravine_radius: [f32; 2],
+    pub ravine_height: f32,
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_y: -128,
+            max_y: 96,
+            water_guard: 0.3,
+            cheese_scale: 56.0,
+            cheese_threshold: 0.62,
+            spaghetti_scale: 72.0,
+            spaghetti_width: 0.035,
+            worm_chance: 0.06,
+            worm_length: 80,
+            worm_radius: [1.6, 3.2],
+            ravine_chance: 0.006,
+            ravine_length: 96,
+            ravine_radius: [1.5, 3.0],
+            ravine_height: 4.0,
+        }
+    }
+}
+
+// isovalue gradient of the carved field per block. close to the terrain's, so SurfaceNets interpolates tunnels as smooth as the terrain.
+const CARVE_GRADIENT: f32 = 0.055;
+
+// the smooth falloff extends a bit outside of the ellipsoid. cells there are only lowered, not carved.
+const CARVE_FALLOFF: f32 = 2.0;
+
+// origin chunks of cached tunnels. the cache is cleared when full, a chunk within reach of ~3000 origins is generated in ~1ms.
+const TUNNEL_CACHE_CAP: usize = 1 << 16;
+
+/// The path of a worm or ravine, the ellipsoids (center, radius) carved along it.
+struct Tunnel {
+    steps: Vec<(Vec3, Vec3)>,
+    // bounds of the carved cells, including the falloff.
+    min: Vec3,
+    max: Vec3,
+}
+
+/// Carves caves into the terrain isovalue field.
+/// Noise caves (cheese, spaghetti) are sampled per cell. Tunnels (worms, ravines) are random walks seeded by their origin chunk,
+/// every chunk within reach carves its own part of the walks, so tunnels are continuous across chunks regardless of load order.
+/// The walks are cached per origin chunk, instead of replayed for every chunk within reach.
+pub struct CaveCarver {
+    pub config: CaveConfig,
+    seed: u64,
+    cheese: Perlin,
+    spaghetti: [Perlin; 2],
+
+    tunnels: Mutex<HashMap<IVec3, Arc<[Tunnel]>>>,
+}
+
+impl CaveCarver {
+    pub fn new(seed: u64, noise_seed: u32, config: CaveConfig) -> Self {
+        Self {
+            config,
+            seed,
+            cheese: Perlin::new(noise_seed.wrapping_add(4)),
+            spaghetti: [Perlin::new(noise_seed.wrapping_add(5)), Perlin::new(noise_seed.wrapping_add(6))],
+            tunnels: Mutex::default(),
+        }
+    }
+
+    /// Carve isovalues of the chunk cells, in local_idx order. negative inside caves, f32::MAX where nothing is carved.
+    /// The terrain isovalue should take the min of it.
+    pub fn carve_chunk(&self, chunkpos: IVec3) -> Vec<f32> {
+        let cfg = &self.config;
+        let mut field = vec![f32::MAX; (Chunk::SIZE * Chunk::SIZE * Chunk::SIZE) as usize];
+        if !cfg.enabled || chunkpos.y + Chunk::SIZE <= cfg.min_y || chunkpos.y >= cfg.max_y {
+            return field;
+        }
+
+        for (i, v) in field.iter_mut().enumerate() {
+            let p = chunkpos + Chunk::local_idx_pos(i as i32);
+            if p.y < cfg.min_y || p.y >= cfg.max_y {
+                continue;
+            }
+            *v = self.noise_caves(p.as_dvec3());
+        }
+
+        self.carve_tunnels(chunkpos, &mut field);
+        field
+    }
+
+    fn noise_caves(&self, p: DVec3) -> f32 {
+        let cfg = &self.config;
+
+        // caverns are flattened, stretched horizontally.
+        let s = cfg.cheese_scale as f64;
+        let n = self.cheese.get([p.x / s, p.y / (s * 0.5), p.z / s]) as f32;
+        let cheese = (cfg.cheese_threshold - n) * CARVE_GRADIENT * cfg.cheese_scale;
+
+        let s = cfg.spaghetti_scale as f64;
+        let p = (p / s).to_array();
+        let n = self.spaghetti[0].get(p).abs().max(self.spaghetti[1].get(p).abs()) as f32;
+        let spaghetti = (n - cfg.spaghetti_width) * CARVE_GRADIENT * cfg.spaghetti_scale;
+
+        cheese.min(spaghetti)
+    }
+
+    fn carve_tunnels(&self, chunkpos: IVec3, field: &mut [f32]) {
+        let cfg = &self.config;
+
+        // chunks farther than the tunnel reach can't carve into this chunk.
+        let reach = cfg.worm_length.max(cfg.ravine_length) as f32 + cfg.worm_radius[1].max(cfg.ravine_radius[1] * cfg.ravine_height);
+        let n = (reach / Chunk::SIZE as f32).ceil() as i32;
+        let (chunk_min, chunk_max) = (chunkpos.as_vec3(), (chunkpos + Chunk::SIZE).as_vec3());
+
+        for dx in -n..=n {
+            for dy in -n..=n {
+                for dz in -n..=n {
+                    let origin = chunkpos + IVec3::new(dx, dy, dz) * Chunk::SIZE;
+                    if origin.y + Chunk::SIZE <= cfg.min_y || origin.y >= cfg.max_y {
+                        continue;
+                    }
+                    for tunnel in self.tunnels(origin).iter() {
+                        if tunnel.max.cmplt(chunk_min).any() || tunnel.min.cmpgt(chunk_max).any() {
+                            continue;
+                        }
+                        for (center, radius) in tunnel.steps.iter() {
+                            self.carve_ellipsoid(*center, *radius, chunkpos, field);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Tunnels started by the origin chunk. generated once and cached.
+    fn tunnels(&self, origin: IVec3) -> Arc<[Tunnel]> {
+        if let Some(tunnels) = self.tunnels.lock().unwrap().get(&origin) {
+            return tunnels.clone();
+        }
+        let cfg = &self.config;
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed ^ hash_ivec3(origin));
+        let mut tunnels = Vec::new();
+
+        if rng.gen::<f32>() < cfg.worm_chance {
+            let start = origin.as_vec3() + Vec3::new(rng.gen(), rng.gen(), rng.gen()) * Chunk::SIZE as f32;
+            let radius = rng.gen_range(cfg.worm_radius[0]..=cfg.worm_radius[1]);
+            tunnels.push(Self::walk(&mut rng, start, cfg.worm_length, radius, 1.0, 0.7));
+        }
+        if rng.gen::<f32>() < cfg.ravine_chance {
+            let start = origin.as_vec3() + Vec3::new(rng.gen(), rng.gen(), rng.gen()) * Chunk::SIZE as f32;
+            let radius = rng.gen_range(cfg.ravine_radius[0]..=cfg.ravine_radius[1]);
+            tunnels.push(Self::walk(&mut rng, start, cfg.ravine_length, radius, cfg.ravine_height, 0.3));
+        }
+
+        let tunnels: Arc<[Tunnel]> = tunnels.into();
+        let mut cache = self.tunnels.lock().unwrap();
+        if cache.len() >= TUNNEL_CACHE_CAP {
+            cache.clear();
+        }
+        cache.insert(origin, tunnels.clone());
+        tunnels
+    }
+
+    // Random walk of a tunnel, ellipsoids along the path. the tunnel is thickest in the middle, tapers at the ends.
+    // `steepness` limits the pitch (radians), ravines stay mostly horizontal.
+    fn walk(rng: &mut ChaCha8Rng, start: Vec3, length: u32, radius: f32, height: f32, steepness: f32) -> Tunnel {
+        let mut pos = start;
+        let mut yaw = rng.gen_range(0.0..TAU);
+        let mut pitch = rng.gen_range(-0.5..0.5) * steepness;
+        let mut dyaw = 0.0;
+        let mut dpitch = 0.0;
+
+        let mut tunnel = Tunnel {
+            steps: Vec::with_capacity(length as usize),
+            min: Vec3::MAX,
+            max: Vec3::MIN,
+        };
+        for i in 0..length {
+            let r = radius * (0.4 + 0.6 * (PI * i as f32 / length as f32).sin());
+            let r = Vec3::new(r, r * height, r);
+            tunnel.steps.push((pos, r));
+            tunnel.min = tunnel.min.min(pos - r - CARVE_FALLOFF);
+            tunnel.max = tunnel.max.max(pos + r + CARVE_FALLOFF);
+
+            pos += Vec3::new(yaw.cos() * pitch.cos(), pitch.sin(), yaw.sin() * pitch.cos());
+            yaw += dyaw * 0.1;
+            pitch = (pitch * 0.8 + dpitch * 0.1).clamp(-steepness, steepness);
+            dyaw = dyaw * 0.75 + rng.gen_range(-1.0..1.0) * 2.0;
+            dpitch = dpitch * 0.9 + rng.gen_range(-1.0..1.0);
+        }
+        tunnel
+    }
+
+    fn carve_ellipsoid(&self, center: Vec3, radius: Vec3, chunkpos: IVec3, field: &mut [f32]) {
+        let min = ((center - radius - CARVE_FALLOFF).floor().as_ivec3() - chunkpos).max(IVec3::ZERO);
+        let max = ((center + radius + CARVE_FALLOFF).ceil().as_ivec3() - chunkpos).min(IVec3::splat(Chunk::SIZE - 1));
+        if min.cmpgt(max).any() {
+            return;
+        }
+        let cfg = &self.config;
+
+        for ly in min.y..=max.y {
+            let y = chunkpos.y + ly;
+            if y < cfg.min_y || y >= cfg.max_y {
+                continue;
+            }
+            for lz in min.z..=max.z {
+                for lx in min.x..=max.x {
+                    let lp = IVec3::new(lx, ly, lz);
+                    let d = ((chunkpos + lp).as_vec3() - center) / radius;
+                    // approximate distance to the ellipsoid surface in blocks.
+                    let val = (d.length() - 1.0) * radius.min_element() * CARVE_GRADIENT;
+
+                    let v = &mut field[Chunk::local_idx(lp)];
+                    *v = v.min(val);
+                }
+            }
+        }
+    }
+}
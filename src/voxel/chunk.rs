@@ -1,9 +1,11 @@
-use std::sync::Weak;
+use std::sync::{RwLock, Weak};
 use bevy::{math::ivec3, prelude::*};
 
+use super::palette::{CellMut, PalettedCells};
+
 
 #[repr(u8)]
-#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize, Default, Reflect)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize, Default, Reflect)]
 pub enum VoxShape {
     #[default]
     Isosurface,
@@ -21,13 +23,21 @@ pub enum VoxShape {
 
     Fence,
 
-    // CustomMesh {
-    //     mesh_id: u16,
-    // }
+    /// a registered model, see `mdl`. `rotation` is in 90 degrees steps around Y.
+    CustomMesh {
+        mesh_id: u16,
+        rotation: u8,
+    },
+
+    /// Fluid e.g. water, of the material `tex_id`. `level` in 1..=Cell::FLUID_LEVELS, the height of the surface in the cell.
+    /// empty for the isosurface, the isovalue is kept for the terrain under the fluid.
+    Fluid {
+        level: u8,
+    },
 }
 
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub struct Cell {
     pub tex_id: u16,
 
@@ -98,22 +108,51 @@ impl Cell {
     pub fn is_obaque_cube(&self) -> bool {
         self.shape_id == VoxShape::Cube && !self.is_tex_empty()
     }
+
+    /// Levels of a full fluid cell.
+    pub const FLUID_LEVELS: u8 = 8;
+
+    pub fn new_fluid(tex_id: u16, level: u8, isovalue: f32) -> Self {
+        Self::new(tex_id, VoxShape::Fluid { level }, isovalue)
+    }
+
+    pub fn is_fluid(&self) -> bool {
+        matches!(self.shape_id, VoxShape::Fluid { .. }) && !self.is_tex_empty()
+    }
+
+    /// 0 if it's not a fluid.
+    pub fn fluid_level(&self) -> u8 {
+        match self.shape_id {
+            VoxShape::Fluid { level } if !self.is_tex_empty() => level,
+            _ => 0,
+        }
+    }
 }
 
 // Chunk is "Heavy" type (big size, stored a lot voxels). thus copy/clone are not allowed.
 pub struct Chunk {
-    // shoud Box?
-    cells: [Cell; 16 * 16 * 16],
+    // Palette-Compressed, a uniform chunk (all air/stone) only takes a few bytes.
+    cells: PalettedCells,
 
     pub chunkpos: IVec3,
 
     pub entity: Entity,
     pub mesh_handle: Handle<Mesh>, // solid terrain
     pub mesh_handle_foliage: Handle<Mesh>,
+    pub mesh_handle_fluid: Handle<Mesh>,
 
     // cached neighbor chunks (if they are not empty even if they are loaded)
     // for Quick Access neighbor voxel, without global find neighbor chunk by chunkpos
-    pub neighbor_chunks: [Option<Weak<Chunk>>; Self::NEIGHBOR_DIR.len()],
+    pub neighbor_chunks: [Option<Weak<RwLock<Chunk>>>; Self::NEIGHBOR_DIR.len()],
+
+    // packed sky and block light of cells, see `lighting`. not saved or synced, clients light chunks on load.
+    // empty (all dark) if the chunk is not lit, e.g. on the server.
+    light: Vec<u8>,
+
+    // Modification Generation. increased on every cell modification.
+    // the chunk is dirty (need to be saved) if it's modified after last save.
+    pub modified_gen: u32,
+    pub saved_gen: u32,
 }
 
 impl Chunk {
@@ -121,38 +160,39 @@ impl Chunk {
 
     pub fn new(chunkpos: IVec3) -> Self {
         Self {
-            cells: [Cell::default(); 16 * 16 * 16],
+            cells: PalettedCells::default(),
             chunkpos,
             neighbor_chunks: Default::default(),
+            light: Vec::new(),
             entity: Entity::PLACEHOLDER,
             mesh_handle: Handle::default(),
             mesh_handle_foliage: Handle::default(),
+            mesh_handle_fluid: Handle::default(),
+            modified_gen: 0,
+            saved_gen: 0,
         }
     }
 
+    pub fn is_dirty(&self) -> bool {
+        self.modified_gen != self.saved_gen
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_gen = self.modified_gen;
+    }
+
     pub fn get_cell(&self, localpos: IVec3) -> &Cell {
-        &self.cells[Chunk::local_idx(localpos)]
+        self.cells.get(Chunk::local_idx(localpos))
     }
 
     pub fn get_cell_neighbor(&self, relpos: IVec3) -> Option<Cell> {
         if Chunk::is_localpos(relpos) {
             Some(*self.get_cell(relpos))
         } else {
-            /*
-            let neib_idx = Chunk::neighbor_idx(relpos)?;
-            self.neighbor_chunks[neib_idx].as_ref()
-                .and_then(|neib_weak| neib_weak.upgrade())
-                .and_then(|neib_chunkptr| neib_chunkptr.read().unwrap())
-                .and_then(|neib_chunk| {
-                    // assert!(neib_chunk.chunkpos == self.chunkpos + Self::NEIGHBOR_DIR[neib_idx] * Chunk::SIZE, "self.chunkpos = {}, neib {} pos {}", self.chunkpos, neib_idx, neib_chunk.chunkpos);
-                    Some(*neib_chunk.get_cell(Chunk::as_localpos(relpos)))
-                })
-            */
-
             let neib_idx = Chunk::neighbor_idx(relpos)?;
             if let Some(neib_weak) = &self.neighbor_chunks[neib_idx] {
                 let neib_chunkptr = neib_weak.upgrade()?;
-                let neib_chunk = neib_chunkptr.as_ref();
+                let neib_chunk = neib_chunkptr.read().unwrap();
                 // assert!(neib_chunk.chunkpos == self.chunkpos + Self::NEIGHBOR_DIR[neib_idx] * Chunk::SIZE, "self.chunkpos = {}, neib {} pos {}", self.chunkpos, neib_idx, neib_chunk.chunkpos);
 
                 return Some(*neib_chunk.get_cell(Chunk::as_localpos(relpos)));
@@ -165,12 +205,58 @@ impl Chunk {
         self.get_cell_neighbor(relpos).unwrap_or(Cell::default())
     }
 
-    pub fn get_cell_mut(&mut self, localpos: IVec3) -> &mut Cell {
-        &mut self.cells[Chunk::local_idx(localpos)]
+    // the modification is written back when the returned CellMut is dropped.
+    pub fn get_cell_mut(&mut self, localpos: IVec3) -> CellMut<'_> {
+        self.modified_gen = self.modified_gen.wrapping_add(1);
+        CellMut::new(&mut self.cells, Chunk::local_idx(localpos))
     }
 
     pub fn set_cell(&mut self, localpos: IVec3, cell: &Cell) {
-        self.cells[Chunk::local_idx(localpos)] = *cell;
+        self.modified_gen = self.modified_gen.wrapping_add(1);
+        self.cells.set(Chunk::local_idx(localpos), cell);
+    }
+
+    pub fn get_light(&self, localpos: IVec3) -> u8 {
+        self.light.get(Chunk::local_idx(localpos)).copied().unwrap_or(0)
+    }
+
+    pub fn get_light_neighbor(&self, relpos: IVec3) -> Option<u8> {
+        if Chunk::is_localpos(relpos) {
+            Some(self.get_light(relpos))
+        } else {
+            let neib_chunkptr = self.neighbor_chunks[Chunk::neighbor_idx(relpos)?].as_ref()?.upgrade()?;
+            let neib_chunk = neib_chunkptr.read().unwrap();
+            Some(neib_chunk.get_light(Chunk::as_localpos(relpos)))
+        }
+    }
+
+    pub fn get_light_rel(&self, relpos: IVec3) -> u8 {
+        self.get_light_neighbor(relpos).unwrap_or(0)
+    }
+
+    pub fn set_light(&mut self, localpos: IVec3, light: u8) {
+        if self.light.is_empty() {
+            self.light = vec![0; Chunk::LOCAL_IDX_CAP];
+        }
+        self.light[Chunk::local_idx(localpos)] = light;
+    }
+
+    pub fn clear_light(&mut self) {
+        self.light.fill(0);
+    }
+
+    pub fn cells(&self) -> &PalettedCells {
+        &self.cells
+    }
+
+    pub fn set_cells(&mut self, cells: PalettedCells) {
+        self.modified_gen = self.modified_gen.wrapping_add(1);
+        self.cells = cells;
+    }
+
+    /// Drop unused palette entries. call after bulk modifications e.g. generation.
+    pub fn compact_cells(&mut self) {
+        self.cells.compact();
     }
 
     pub fn is_neighbors_complete(&self) -> bool {
@@ -1,18 +1,392 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 
-struct ChunkLoader {
+use bevy::{prelude::*, tasks::IoTaskPool, utils::HashMap};
+use serde::{Deserialize, Serialize};
 
-    save_dir: Path,
+use super::{worldgen::PendingWrites, Cell, Chunk, VoxShape, WorldGenPreset};
 
+// Region File Layout:
+//   Header:  REGION_CAP entries of (sector_offset: u32, byte_len: u32), little endian. (0, 0) means the chunk is not stored.
+//   Sectors: compressed chunk payloads. each payload begins at a sector boundary and may span multiple contiguous sectors.
+//
+// The header occupies the first HEADER_SECTORS sectors. A rewritten chunk reuses its old sectors if the new payload fits,
+// otherwise it's appended to the end of file. (the old sectors leaks, todo: compaction)
+pub struct RegionFile {
+    file: File,
+    entries: Vec<(u32, u32)>,
+    num_sectors: u32,
+}
+
+impl RegionFile {
+    /// Chunks per axis of a Region.
+    pub const SIZE: i32 = 16;
+    pub const REGION_CAP: usize = (Self::SIZE * Self::SIZE * Self::SIZE) as usize;
+
+    const SECTOR_SIZE: u32 = 512;
+    const HEADER_SECTORS: u32 = (Self::REGION_CAP * 8) as u32 / Self::SECTOR_SIZE;
+
+    pub fn open(path: &PathBuf) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        let len = file.metadata()?.len();
+        let mut header = vec![0u8; Self::REGION_CAP * 8];
+        if len == 0 {
+            file.write_all(&header)?;
+        } else {
+            file.read_exact(&mut header)?;
+        }
+
+        let entries = header
+            .chunks_exact(8)
+            .map(|e| (u32::from_le_bytes(e[0..4].try_into().unwrap()), u32::from_le_bytes(e[4..8].try_into().unwrap())))
+            .collect();
+
+        let num_sectors = (len.max(header.len() as u64) as u32).div_ceil(Self::SECTOR_SIZE).max(Self::HEADER_SECTORS);
+
+        Ok(Self { file, entries, num_sectors })
+    }
+
+    // the entry index of a chunk in the region
+    fn entry_idx(chunkpos: IVec3) -> usize {
+        let p = (chunkpos / Chunk::SIZE).rem_euclid(IVec3::splat(Self::SIZE));
+        ((p.x * Self::SIZE + p.y) * Self::SIZE + p.z) as usize
+    }
+
+    pub fn as_regionpos(chunkpos: IVec3) -> IVec3 {
+        (chunkpos / Chunk::SIZE).div_euclid(IVec3::splat(Self::SIZE))
+    }
+
+    pub fn read_chunk(&mut self, chunkpos: IVec3) -> io::Result<Option<Vec<u8>>> {
+        let (sector_offset, byte_len) = self.entries[Self::entry_idx(chunkpos)];
+        if byte_len == 0 {
+            return Ok(None);
+        }
+
+        let mut data = vec![0u8; byte_len as usize];
+        self.file.seek(SeekFrom::Start(sector_offset as u64 * Self::SECTOR_SIZE as u64))?;
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    pub fn write_chunk(&mut self, chunkpos: IVec3, data: &[u8]) -> io::Result<()> {
+        let idx = Self::entry_idx(chunkpos);
+        let (old_offset, old_len) = self.entries[idx];
+
+        let num_sectors_needed = (data.len() as u32).div_ceil(Self::SECTOR_SIZE);
+        let sector_offset = if old_len != 0 && old_len.div_ceil(Self::SECTOR_SIZE) >= num_sectors_needed {
+            old_offset
+        } else {
+            let offset = self.num_sectors;
+            self.num_sectors += num_sectors_needed;
+            offset
+        };
+
+        // Payload, padded to the sector boundary.
+        let mut buf = data.to_vec();
+        buf.resize((num_sectors_needed * Self::SECTOR_SIZE) as usize, 0);
+        self.file.seek(SeekFrom::Start(sector_offset as u64 * Self::SECTOR_SIZE as u64))?;
+        self.file.write_all(&buf)?;
+
+        // Header Entry
+        let entry = (sector_offset, data.len() as u32);
+        self.file.seek(SeekFrom::Start(idx as u64 * 8))?;
+        self.file.write_all(&entry.0.to_le_bytes())?;
+        self.file.write_all(&entry.1.to_le_bytes())?;
+        self.entries[idx] = entry;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+// Chunk Payload. Cells are split into 2 channels and Run-Length Encoded separately, in local_idx order:
+// materials (tex_id, shape_id) are dominated by long runs of air/stone/water, while isovalues only vary near the surface.
+#[derive(Serialize, Deserialize)]
+struct ChunkPayload {
+    version: u8,
+    materials: Vec<(u16, u16, VoxShape)>, // (run_len, tex_id, shape_id)
+    isovals: Vec<(u16, u8)>,              // (run_len, isoval)
+}
+
+impl ChunkPayload {
+    const VERSION: u8 = 1;
+}
+
+fn bincode_options() -> impl bincode::Options {
+    use bincode::Options;
+    bincode::DefaultOptions::new().with_varint_encoding()
+}
+
+fn rle_push<T: PartialEq>(runs: &mut Vec<(u16, T)>, val: T) {
+    match runs.last_mut() {
+        Some(last) if last.1 == val => last.0 += 1,
+        _ => runs.push((1, val)),
+    }
+}
+
+pub fn encode_chunk(chunk: &Chunk) -> Vec<u8> {
+    let mut materials = Vec::new();
+    let mut isovals = Vec::new();
+
+    for i in 0..Chunk::LOCAL_IDX_CAP {
+        let c = chunk.get_cell(Chunk::local_idx_pos(i as i32));
+
+        rle_push(&mut materials, (c.tex_id, c.shape_id));
+        rle_push(&mut isovals, c.isoval);
+    }
+
+    let payload = ChunkPayload {
+        version: ChunkPayload::VERSION,
+        materials: materials.into_iter().map(|(n, (tex_id, shape_id))| (n, tex_id, shape_id)).collect(),
+        isovals,
+    };
+    bincode::Options::serialize(bincode_options(), &payload).unwrap()
+}
+
+pub fn decode_chunk(data: &[u8], chunk: &mut Chunk) -> anyhow::Result<()> {
+    let payload: ChunkPayload = bincode::Options::deserialize(bincode_options(), data)?;
+    if payload.version != ChunkPayload::VERSION {
+        anyhow::bail!("unsupported chunk payload version {}", payload.version);
+    }
+    let num_materials: usize = payload.materials.iter().map(|r| r.0 as usize).sum();
+    let num_isovals: usize = payload.isovals.iter().map(|r| r.0 as usize).sum();
+    if num_materials != Chunk::LOCAL_IDX_CAP || num_isovals != Chunk::LOCAL_IDX_CAP {
+        anyhow::bail!("corrupted chunk payload, {} materials, {} isovals", num_materials, num_isovals);
+    }
+
+    let mut i = 0;
+    for (n, tex_id, shape_id) in payload.materials {
+        for _ in 0..n {
+            chunk.set_cell(Chunk::local_idx_pos(i), &Cell::new(tex_id, shape_id, 0.0));
+            i += 1;
+        }
+    }
+    let mut i = 0;
+    for (n, isoval) in payload.isovals {
+        for _ in 0..n {
+            chunk.get_cell_mut(Chunk::local_idx_pos(i)).isoval = isoval;
+            i += 1;
+        }
+    }
+    chunk.compact_cells();
+    Ok(())
+}
+
+/// World Metadata, stored in `{save_dir}/world.json`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WorldMeta {
+    pub seed: u64,
+    pub time_created: u64,
+    // worlds created before presets are generated by the default.
+    #[serde(default)]
+    pub preset: WorldGenPreset,
+}
+
+/// Load/Save Chunks from/to the Region Files of a world save directory.
+/// Shared by async chunk loading/saving tasks, region files are cached and accessed exclusively.
+pub struct ChunkLoader {
+    save_dir: PathBuf,
+
+    regions: Mutex<HashMap<IVec3, RegionFile>>,
+
+    // encoded chunks waiting to be written by background tasks.
+    // loading reads them first, so a chunk reloaded right after unload won't read stale data from the region file.
+    pending_saves: Mutex<HashMap<IVec3, Arc<Vec<u8>>>>,
+
+    // the latest pending writes waiting to be saved by background tasks, and the lock of writing it.
+    pending_writes_save: Mutex<Option<PendingWrites>>,
+    pending_writes_io: Mutex<()>,
 }
 
 impl ChunkLoader {
+    pub fn new(save_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            save_dir: save_dir.into(),
+            regions: Mutex::default(),
+            pending_saves: Mutex::default(),
+            pending_writes_save: Mutex::default(),
+            pending_writes_io: Mutex::default(),
+        }
+    }
+
+    pub fn save_dir(&self) -> &Path {
+        &self.save_dir
+    }
+
+    /// return None if the world haven't been created.
+    pub fn load_meta(&self) -> anyhow::Result<Option<WorldMeta>> {
+        let path = self.save_dir.join("world.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&std::fs::read_to_string(path)?)?))
+    }
 
-    fn load_chunk(chunk: &mut Chunk) {
+    pub fn save_meta(&self, meta: &WorldMeta) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.save_dir)?;
+        std::fs::write(self.save_dir.join("world.json"), serde_json::to_string_pretty(meta)?)?;
+        Ok(())
+    }
+
+    /// Pending feature writes of WorldGen, targeting chunks haven't been loaded since.
+    pub fn load_pending_writes(&self) -> anyhow::Result<PendingWrites> {
+        let path = self.save_dir.join("pending_writes.bin");
+        if !path.exists() {
+            return Ok(HashMap::default());
+        }
+        Ok(bincode::Options::deserialize(bincode_options(), &std::fs::read(path)?)?)
+    }
 
+    pub fn save_pending_writes(&self, pending_writes: &PendingWrites) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.save_dir)?;
+        std::fs::write(self.save_dir.join("pending_writes.bin"), bincode::Options::serialize(bincode_options(), pending_writes)?)?;
+        Ok(())
     }
 
-    fn save_chunk(chunk: &mut Chunk) {
+    /// Serialize and write the pending writes in background.
+    pub fn save_pending_writes_async(self: &Arc<Self>, pending_writes: PendingWrites) {
+        *self.pending_writes_save.lock().unwrap() = Some(pending_writes);
 
+        let chunk_loader = self.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                if let Err(err) = chunk_loader.write_pending_writes() {
+                    error!("Failed to save pending writes: {}", err);
+                }
+            })
+            .detach();
     }
 
-}
\ No newline at end of file
+    // write the latest pending writes save, if not written yet. kept for flush() if failed.
+    // taken under the io lock, so an older save never overwrites a newer one.
+    fn write_pending_writes(&self) -> anyhow::Result<()> {
+        let _io = self.pending_writes_io.lock().unwrap();
+        let Some(pending_writes) = self.pending_writes_save.lock().unwrap().take() else {
+            return Ok(());
+        };
+        let result = self.save_pending_writes(&pending_writes);
+        if result.is_err() {
+            self.pending_writes_save.lock().unwrap().get_or_insert(pending_writes);
+        }
+        result
+    }
+
+    fn region_path(&self, regionpos: IVec3) -> PathBuf {
+        self.save_dir.join("region").join(format!("r.{}.{}.{}.bin", regionpos.x, regionpos.y, regionpos.z))
+    }
+
+    // access the region of the chunk. return None if the region file doesn't exist and `create` is false.
+    fn with_region<R>(&self, chunkpos: IVec3, create: bool, func: impl FnOnce(&mut RegionFile) -> io::Result<R>) -> io::Result<Option<R>> {
+        let regionpos = RegionFile::as_regionpos(chunkpos);
+        let mut regions = self.regions.lock().unwrap();
+
+        if !regions.contains_key(&regionpos) {
+            let path = self.region_path(regionpos);
+            if !create && !path.exists() {
+                return Ok(None);
+            }
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            regions.insert(regionpos, RegionFile::open(&path)?);
+        }
+
+        func(regions.get_mut(&regionpos).unwrap()).map(Some)
+    }
+
+    /// return false if the chunk haven't been saved before. (should generate it)
+    pub fn load_chunk(&self, chunk: &mut Chunk) -> anyhow::Result<bool> {
+        let chunkpos = chunk.chunkpos;
+
+        let pending = self.pending_saves.lock().unwrap().get(&chunkpos).cloned();
+        let data = match pending {
+            Some(data) => data.to_vec(),
+            None => match self.with_region(chunkpos, false, |region| region.read_chunk(chunkpos))?.flatten() {
+                Some(data) => data,
+                None => return Ok(false),
+            },
+        };
+
+        decode_chunk(&data, chunk)?;
+        Ok(true)
+    }
+
+    pub fn save_chunk(&self, chunk: &Chunk) -> anyhow::Result<()> {
+        let data = encode_chunk(chunk);
+        self.with_region(chunk.chunkpos, true, |region| region.write_chunk(chunk.chunkpos, &data))?;
+        Ok(())
+    }
+
+    /// Encode the chunk now, and write it to the region file in background.
+    pub fn save_chunk_async(self: &Arc<Self>, chunk: &Chunk) {
+        let chunkpos = chunk.chunkpos;
+        let data = Arc::new(encode_chunk(chunk));
+        self.pending_saves.lock().unwrap().insert(chunkpos, data.clone());
+
+        let chunk_loader = self.clone();
+        IoTaskPool::get()
+            .spawn(async move {
+                if let Err(err) = chunk_loader.write_pending(chunkpos, &data) {
+                    error!("Failed to save chunk {}: {}", chunkpos, err);
+                }
+            })
+            .detach();
+    }
+
+    fn is_latest_pending(&self, chunkpos: IVec3, data: &Arc<Vec<u8>>) -> bool {
+        self.pending_saves.lock().unwrap().get(&chunkpos).is_some_and(|d| Arc::ptr_eq(d, data))
+    }
+
+    // write a pending save, then it's no longer pending unless superseded meanwhile.
+    // pending_saves is not locked during the disk io, the main thread inserts into it.
+    // checked again under the region lock, so an older save never overwrites a newer one, or one already written by flush().
+    fn write_pending(&self, chunkpos: IVec3, data: &Arc<Vec<u8>>) -> io::Result<()> {
+        self.with_region(chunkpos, true, |region| {
+            if self.is_latest_pending(chunkpos, data) {
+                region.write_chunk(chunkpos, data)?;
+            }
+            Ok(())
+        })?;
+
+        let mut pending_saves = self.pending_saves.lock().unwrap();
+        if pending_saves.get(&chunkpos).is_some_and(|d| Arc::ptr_eq(d, data)) {
+            pending_saves.remove(&chunkpos);
+        }
+        Ok(())
+    }
+
+    /// Write all pending saves and pending writes, and sync region files to disk.
+    /// Failed saves are kept pending, the rest are still written.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let pending: Vec<_> = self.pending_saves.lock().unwrap().iter().map(|(p, d)| (*p, d.clone())).collect();
+
+        let mut num_failed = 0;
+        for (chunkpos, data) in pending {
+            if let Err(err) = self.write_pending(chunkpos, &data) {
+                error!("Failed to save chunk {}: {}", chunkpos, err);
+                num_failed += 1;
+            }
+        }
+        if let Err(err) = self.write_pending_writes() {
+            error!("Failed to save pending writes: {}", err);
+            num_failed += 1;
+        }
+        for region in self.regions.lock().unwrap().values_mut() {
+            if let Err(err) = region.flush() {
+                error!("Failed to flush region file: {}", err);
+                num_failed += 1;
+            }
+        }
+
+        if num_failed > 0 {
+            anyhow::bail!("{} saves failed", num_failed);
+        }
+        Ok(())
+    }
+}
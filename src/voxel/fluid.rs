@@ -0,0 +1,389 @@
+use std::sync::RwLockReadGuard;
+
+use bevy::{
+    math::ivec3,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use super::{mtl, Cell, Chunk, ChunkPtr, VoxShape};
+use crate::util::iter;
+
+// Cellular-automaton fluids, simulated on the server. a fluid cell has a level 1..=Cell::FLUID_LEVELS.
+// Sources are the full level cells, e.g. the sea, they never change by themselves.
+// A cell becomes the highest fluid flowing into it, or empty if none:
+//   from the fluid above, falling (level FLUID_LEVELS - 1),
+//   from the fluids beside, level - 1. a fluid only spreads sideways on a floor, a solid cell or a source.
+//   a cell between 2 sources on a floor becomes a source too.
+// New cells of a tick are computed from the cells before the tick, then applied together. thus the result doesn't
+// depend on the order of cells, and cells are picked in a fixed order when the tick budget is exceeded. deterministic.
+
+const HORIZONTAL: [IVec3; 4] = [ivec3(1, 0, 0), ivec3(-1, 0, 0), ivec3(0, 0, 1), ivec3(0, 0, -1)];
+
+/// Fluid cells waiting to be updated, of the loaded chunks.
+#[derive(Default)]
+pub struct FluidSim {
+    scheduled: HashSet<IVec3>,
+}
+
+impl FluidSim {
+    pub fn schedule(&mut self, p: IVec3) {
+        self.scheduled.insert(p);
+    }
+
+    /// Schedule the cell and the cells depending on it, e.g. after the cell is modified.
+    /// its 6 neighbors, and the 4 cells beside the above one (spreading over the cell).
+    pub fn schedule_around(&mut self, p: IVec3) {
+        self.schedule(p);
+        self.schedule(p + IVec3::Y);
+        self.schedule(p - IVec3::Y);
+        for d in HORIZONTAL {
+            self.schedule(p + d);
+            self.schedule(p + d + IVec3::Y);
+        }
+    }
+
+    pub fn num_scheduled(&self) -> usize {
+        self.scheduled.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.scheduled.clear();
+    }
+
+    /// Schedule unsettled cells of the newly loaded chunk, and the cells around it, which were not updated since
+    /// they depend on the unloaded chunk.
+    pub fn on_chunk_loaded(&mut self, chunks: &HashMap<IVec3, ChunkPtr>, chunkpos: IVec3) {
+        let mut cells = CellReader::new(chunks);
+        iter::iter_xzy(Chunk::SIZE + 2, |rp| {
+            let p = chunkpos - 1 + rp;
+            if is_unsettled(&mut cells, p) {
+                self.scheduled.insert(p);
+            }
+        });
+    }
+
+    /// Update at most `budget` scheduled cells, lower cells first. cells of the unloaded chunks are dropped.
+    /// return the changed cells of each chunk.
+    pub fn tick(&mut self, chunks: &HashMap<IVec3, ChunkPtr>, budget: usize) -> Vec<(IVec3, Vec<(u16, Cell)>)> {
+        let mut positions: Vec<IVec3> = self.scheduled.iter().cloned().collect();
+        positions.sort_unstable_by_key(|p| (p.y, p.z, p.x));
+        positions.truncate(budget);
+
+        let mut changes = Vec::new();
+        {
+            let mut cells = CellReader::new(chunks);
+            for p in positions.iter() {
+                self.scheduled.remove(p);
+                if let Some(c) = next_cell(&mut cells, *p) {
+                    changes.push((*p, c));
+                }
+            }
+        }
+
+        // don't hold the read lock while writing chunks.
+        let mut modified: HashMap<IVec3, Vec<(u16, Cell)>> = HashMap::default();
+        for (p, c) in changes {
+            let (chunkpos, lp) = (Chunk::as_chunkpos(p), Chunk::as_localpos(p));
+            chunks[&chunkpos].write().unwrap().set_cell(lp, &c);
+            modified.entry(chunkpos).or_default().push((Chunk::local_idx(lp) as u16, c));
+            self.schedule_around(p);
+        }
+        let mut modified: Vec<_> = modified.into_iter().collect();
+        modified.sort_unstable_by_key(|(cp, _)| (cp.y, cp.z, cp.x));
+        modified
+    }
+}
+
+fn is_source(c: &Cell) -> bool {
+    c.fluid_level() == Cell::FLUID_LEVELS
+}
+
+// empty cells, a fluid may flow into.
+fn can_flow_into(c: &Cell) -> bool {
+    !c.is_fluid() && c.is_tex_empty() && c.is_isoval_empty()
+}
+
+// solid cells or sources, a fluid spreads on.
+fn is_floor(c: &Cell) -> bool {
+    is_source(c) || !(c.is_fluid() || can_flow_into(c))
+}
+
+// The cell would change by the next update. flowing fluids, or empty cells next to a fluid.
+fn is_unsettled(cells: &mut CellReader, p: IVec3) -> bool {
+    let Some(c) = cells.get(p) else {
+        return false;
+    };
+    if c.is_fluid() {
+        return !is_source(&c);
+    }
+    let mut flowing_in = HORIZONTAL.iter().chain([IVec3::Y].iter());
+    can_flow_into(&c) && flowing_in.any(|d| cells.get(p + *d).is_some_and(|n| n.is_fluid()))
+}
+
+// The new cell after the update, None if it's unchanged, or it depends on unloaded chunks.
+fn next_cell(cells: &mut CellReader, p: IVec3) -> Option<Cell> {
+    let c = cells.get(p)?;
+    if is_source(&c) || !(c.is_fluid() || can_flow_into(&c)) {
+        return None;
+    }
+    let above = cells.get(p + IVec3::Y)?;
+    let below = cells.get(p - IVec3::Y)?;
+
+    // (tex_id, level) of the highest fluid flowing into the cell. level 0: none.
+    let mut flow = (mtl::NIL, 0);
+    if above.is_fluid() {
+        flow = (above.tex_id, Cell::FLUID_LEVELS - 1);
+    }
+    let mut sources = Vec::new();
+    for d in HORIZONTAL {
+        let n = cells.get(p + d)?;
+        if !n.is_fluid() {
+            continue;
+        }
+        if is_source(&n) {
+            sources.push(n.tex_id);
+        }
+        if !is_floor(&cells.get(p + d - IVec3::Y)?) {
+            continue;
+        }
+        let level = n.fluid_level().saturating_sub(1);
+        if level > flow.1 {
+            flow = (n.tex_id, level);
+        }
+    }
+    if sources.len() >= 2 && is_floor(&below) {
+        flow = (sources[0], Cell::FLUID_LEVELS);
+    }
+
+    let mut new = c;
+    match flow {
+        (tex_id, level) if level > 0 => {
+            new.tex_id = tex_id;
+            new.shape_id = VoxShape::Fluid { level };
+        }
+        // drained. the isovalue is kept.
+        _ if c.is_fluid() => {
+            new.tex_id = mtl::NIL;
+            new.shape_id = VoxShape::Isosurface;
+        }
+        _ => {}
+    }
+    (new != c).then_some(new)
+}
+
+// Reads cells of the loaded chunks. keeps the last chunk locked, since cells are mostly read near each other.
+struct CellReader<'a> {
+    chunks: &'a HashMap<IVec3, ChunkPtr>,
+    last: Option<(IVec3, RwLockReadGuard<'a, Chunk>)>,
+}
+
+impl<'a> CellReader<'a> {
+    fn new(chunks: &'a HashMap<IVec3, ChunkPtr>) -> Self {
+        Self { chunks, last: None }
+    }
+
+    fn get(&mut self, p: IVec3) -> Option<Cell> {
+        let chunkpos = Chunk::as_chunkpos(p);
+        if !matches!(&self.last, Some((cp, _)) if *cp == chunkpos) {
+            self.last = None;
+            self.last = Some((chunkpos, self.chunks.get(&chunkpos)?.read().unwrap()));
+        }
+        self.last.as_ref().map(|(_, chunk)| *chunk.get_cell(Chunk::as_localpos(p)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+
+    fn stone() -> Cell {
+        Cell::new(mtl::STONE, VoxShape::Cube, 0.0)
+    }
+
+    fn air() -> Cell {
+        Cell::new(mtl::NIL, VoxShape::Isosurface, -0.5)
+    }
+
+    fn water(level: u8) -> Cell {
+        Cell::new_fluid(mtl::WATER, level, -0.5)
+    }
+
+    // 2x1x2 chunks at y=0, stone below y=4, air above.
+    fn new_world() -> HashMap<IVec3, ChunkPtr> {
+        let mut chunks = HashMap::default();
+        for (x, z) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            let chunkpos = IVec3::new(x, 0, z) * Chunk::SIZE;
+            let mut chunk = Chunk::new(chunkpos);
+            iter::iter_xzy(Chunk::SIZE, |lp| chunk.set_cell(lp, &if lp.y < 4 { stone() } else { air() }));
+            chunks.insert(chunkpos, Arc::new(RwLock::new(chunk)));
+        }
+        chunks
+    }
+
+    fn get(chunks: &HashMap<IVec3, ChunkPtr>, p: IVec3) -> Cell {
+        *chunks[&Chunk::as_chunkpos(p)].read().unwrap().get_cell(Chunk::as_localpos(p))
+    }
+
+    fn set(chunks: &HashMap<IVec3, ChunkPtr>, sim: &mut FluidSim, p: IVec3, c: &Cell) {
+        chunks[&Chunk::as_chunkpos(p)].write().unwrap().set_cell(Chunk::as_localpos(p), c);
+        sim.schedule_around(p);
+    }
+
+    // tick until settled. return the number of ticks.
+    fn run(chunks: &HashMap<IVec3, ChunkPtr>, sim: &mut FluidSim, budget: usize) -> usize {
+        let mut ticks = 0;
+        while sim.num_scheduled() != 0 {
+            sim.tick(chunks, budget);
+            ticks += 1;
+            assert!(ticks < 10000, "not settled");
+        }
+        ticks
+    }
+
+    #[test]
+    fn spreads_from_source() {
+        let chunks = new_world();
+        let mut sim = FluidSim::default();
+        let src = IVec3::new(16, 4, 16);
+        set(&chunks, &mut sim, src, &water(Cell::FLUID_LEVELS));
+        run(&chunks, &mut sim, usize::MAX);
+
+        // a diamond on the floor across the 4 chunks, 1 level lower per cell.
+        iter::iter_aabb(9, 0, |rp| {
+            let dist = rp.x.abs() + rp.z.abs();
+            let level = (Cell::FLUID_LEVELS as i32 - dist).max(0) as u8;
+            assert_eq!(get(&chunks, src + rp).fluid_level(), level, "at {}", rp);
+            assert!(get(&chunks, src + rp + IVec3::Y).is_tex_empty());
+        });
+    }
+
+    #[test]
+    fn falls_then_spreads() {
+        let chunks = new_world();
+        let mut sim = FluidSim::default();
+        // a source on a pillar, falls down beside it.
+        let pillar = IVec3::new(8, 4, 8);
+        for y in 0..3 {
+            set(&chunks, &mut sim, pillar + IVec3::Y * y, &stone());
+        }
+        let src = pillar + IVec3::Y * 3;
+        set(&chunks, &mut sim, src, &water(Cell::FLUID_LEVELS));
+        run(&chunks, &mut sim, usize::MAX);
+
+        // the falling column beside the pillar, then spreads on the floor from level FLUID_LEVELS - 1.
+        for y in 0..4 {
+            assert_eq!(get(&chunks, pillar + IVec3::X + IVec3::Y * y).fluid_level(), Cell::FLUID_LEVELS - 1);
+        }
+        // doesn't spread sideways while falling.
+        assert!(!get(&chunks, src + IVec3::X * 2).is_fluid());
+        assert!(!get(&chunks, pillar + IVec3::X * 2 + IVec3::Y).is_fluid());
+        assert_eq!(get(&chunks, pillar + IVec3::X * 2).fluid_level(), Cell::FLUID_LEVELS - 2);
+        assert_eq!(get(&chunks, pillar + IVec3::X * 7).fluid_level(), 1);
+        assert!(!get(&chunks, pillar + IVec3::X * 8).is_fluid());
+    }
+
+    #[test]
+    fn drains_without_source() {
+        let chunks = new_world();
+        let mut sim = FluidSim::default();
+        let src = IVec3::new(10, 4, 10);
+        set(&chunks, &mut sim, src, &water(Cell::FLUID_LEVELS));
+        run(&chunks, &mut sim, usize::MAX);
+        assert!(get(&chunks, src + IVec3::X * 3).is_fluid());
+
+        set(&chunks, &mut sim, src, &air());
+        run(&chunks, &mut sim, usize::MAX);
+        iter::iter_aabb(9, 1, |rp| assert!(!get(&chunks, src + rp).is_fluid(), "at {}", rp));
+    }
+
+    #[test]
+    fn infinite_source() {
+        let chunks = new_world();
+        let mut sim = FluidSim::default();
+        let p = IVec3::new(5, 4, 5);
+        set(&chunks, &mut sim, p - IVec3::X, &water(Cell::FLUID_LEVELS));
+        set(&chunks, &mut sim, p + IVec3::X, &water(Cell::FLUID_LEVELS));
+        run(&chunks, &mut sim, usize::MAX);
+        assert_eq!(get(&chunks, p).fluid_level(), Cell::FLUID_LEVELS);
+
+        // still a source after removing one of them.
+        set(&chunks, &mut sim, p + IVec3::X, &air());
+        run(&chunks, &mut sim, usize::MAX);
+        assert_eq!(get(&chunks, p).fluid_level(), Cell::FLUID_LEVELS);
+        assert_eq!(get(&chunks, p + IVec3::X).fluid_level(), Cell::FLUID_LEVELS - 1);
+    }
+
+    #[test]
+    fn waits_for_unloaded_chunks() {
+        let mut chunks = new_world();
+        let unloaded = chunks.remove(&IVec3::new(16, 0, 0)).unwrap();
+        let mut sim = FluidSim::default();
+        let src = IVec3::new(12, 4, 4);
+        set(&chunks, &mut sim, src, &water(Cell::FLUID_LEVELS));
+        run(&chunks, &mut sim, usize::MAX);
+        assert_eq!(get(&chunks, src + IVec3::X * 2).fluid_level(), Cell::FLUID_LEVELS - 2);
+        // next to the unloaded chunk.
+        assert!(!get(&chunks, src + IVec3::X * 3).is_fluid());
+
+        // flows into the chunk once it's loaded.
+        chunks.insert(IVec3::new(16, 0, 0), unloaded);
+        sim.on_chunk_loaded(&chunks, IVec3::new(16, 0, 0));
+        run(&chunks, &mut sim, usize::MAX);
+        assert_eq!(get(&chunks, src + IVec3::X * 4).fluid_level(), Cell::FLUID_LEVELS - 4);
+        assert_eq!(get(&chunks, src + IVec3::X * 7).fluid_level(), 1);
+        assert!(!get(&chunks, src + IVec3::X * 8).is_fluid());
+    }
+
+    #[test]
+    fn deterministic() {
+        let simulate = |budget: usize, reversed: bool| {
+            let chunks = new_world();
+            let mut sim = FluidSim::default();
+            let mut edits = [
+                (IVec3::new(3, 4, 3), water(Cell::FLUID_LEVELS)),
+                (IVec3::new(20, 8, 12), water(Cell::FLUID_LEVELS)),
+                (IVec3::new(12, 4, 20), stone()),
+                (IVec3::new(12, 3, 12), air()),
+                (IVec3::new(12, 2, 12), air()),
+            ];
+            if reversed {
+                edits.reverse();
+            }
+            for (p, c) in edits.iter() {
+                set(&chunks, &mut sim, *p, c);
+            }
+            let mut log = Vec::new();
+            while sim.num_scheduled() != 0 {
+                log.push(sim.tick(&chunks, budget));
+            }
+            let mut cells = Vec::new();
+            for chunkpos in [IVec3::new(0, 0, 0), IVec3::new(16, 0, 0), IVec3::new(0, 0, 16), IVec3::new(16, 0, 16)] {
+                iter::iter_xzy(Chunk::SIZE, |lp| cells.push(get(&chunks, chunkpos + lp)));
+            }
+            (log, cells)
+        };
+        let (log, cells) = simulate(16, false);
+        assert!(log.len() > 1);
+        assert_eq!((log, cells.clone()), simulate(16, true));
+        // the settled state doesn't depend on the budget.
+        assert_eq!(cells, simulate(usize::MAX, false).1);
+    }
+
+    #[test]
+    fn budget_limits_updates() {
+        let chunks = new_world();
+        let mut sim = FluidSim::default();
+        set(&chunks, &mut sim, IVec3::new(16, 4, 16), &water(Cell::FLUID_LEVELS));
+        let mut ticks = 0;
+        while sim.num_scheduled() != 0 {
+            let modified = sim.tick(&chunks, 4);
+            assert!(modified.iter().map(|(_, cells)| cells.len()).sum::<usize>() <= 4);
+            ticks += 1;
+        }
+        assert!(ticks > 100);
+        assert_eq!(get(&chunks, IVec3::new(23, 4, 16)).fluid_level(), 1);
+    }
+}
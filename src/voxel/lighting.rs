@@ -0,0 +1,344 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    math::{ivec3, vec2},
+    prelude::*,
+    utils::HashSet,
+};
+
+use super::{mdl, Cell, Chunk, ChunkPtr, ChunkSystem, VoxShape};
+use crate::util::iter;
+
+// Flood-fill lighting. cells have 2 channels of light levels 0..=MAX_LIGHT, packed in a u8 (see `Chunk::get_light`):
+// Sky light from the open sky above, it goes straight down through clear cells without decreasing.
+// Block light from emissive cells, e.g. lanterns.
+// Light decreases by 1 per cell (more through fluids), and doesn't pass opaque cells.
+
+/// Light level of the open sky and the brightest emissive cells.
+pub const MAX_LIGHT: u8 = 15;
+
+// channels, as the shift in the packed light. sky light is the high 4 bits, block light is the low 4 bits.
+const SKY: u8 = 4;
+const BLOCK: u8 = 0;
+const CHANNELS: [u8; 2] = [SKY, BLOCK];
+
+const DIRS: [IVec3; 6] = [
+    ivec3(-1, 0, 0),
+    ivec3(1, 0, 0),
+    ivec3(0, -1, 0),
+    ivec3(0, 1, 0),
+    ivec3(0, 0, -1),
+    ivec3(0, 0, 1),
+];
+
+pub fn sky_light(light: u8) -> u8 {
+    light >> SKY & 15
+}
+
+pub fn block_light(light: u8) -> u8 {
+    light >> BLOCK & 15
+}
+
+fn channel(light: u8, ch: u8) -> u8 {
+    light >> ch & 15
+}
+
+fn with_channel(light: u8, ch: u8, level: u8) -> u8 {
+    light & !(15 << ch) | level << ch
+}
+
+/// (sky, block) light levels in [0, 1]. for vertices.
+pub fn light_levels(light: u8) -> Vec2 {
+    vec2(sky_light(light) as f32, block_light(light) as f32) / MAX_LIGHT as f32
+}
+
+/// Brightness of a light level in [0, 1]. each level is 0.8x as bright as the next, level 0 is dark. as same as the terrain shader.
+pub fn brightness(level: f32) -> f32 {
+    if level > 0.0 {
+        0.8f32.powf((1.0 - level) * MAX_LIGHT as f32)
+    } else {
+        0.0
+    }
+}
+
+/// Light decrease through the cell, besides the 1 per cell. MAX_LIGHT if the cell is opaque.
+pub fn light_opacity(c: &Cell) -> u8 {
+    if c.is_obaque_cube() {
+        MAX_LIGHT
+    } else if c.is_fluid() {
+        1
+    } else if c.is_isoval_empty() {
+        0
+    } else {
+        MAX_LIGHT
+    }
+}
+
+/// Block light emitted by the cell.
+pub fn light_emission(c: &Cell) -> u8 {
+    match c.shape_id {
+        VoxShape::CustomMesh { mesh_id, .. } if !c.is_tex_empty() => mdl::EMISSION.get(mesh_id as usize).copied().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+// the sky is assumed open above the top cells of a chunk, if the chunk above is not loaded.
+fn is_open_sky(chunk_sys: &impl ChunkSystem, p: IVec3) -> bool {
+    Chunk::as_localpos(p).y == Chunk::SIZE - 1 && !chunk_sys.has_chunk(Chunk::as_chunkpos(p) + IVec3::Y * Chunk::SIZE)
+}
+
+// light of the cell itself, before propagation.
+fn source_light(c: &Cell, open_sky: bool) -> u8 {
+    let opacity = light_opacity(c);
+    let sky = if open_sky && opacity < MAX_LIGHT { MAX_LIGHT - opacity } else { 0 };
+    with_channel(light_emission(c), SKY, sky)
+}
+
+trait LightAccess {
+    // the cell and its light. None if the chunk is not loaded.
+    fn get(&mut self, p: IVec3) -> Option<(Cell, u8)>;
+
+    fn set(&mut self, p: IVec3, light: u8);
+}
+
+// Cells of the loaded chunks, in world position. only one chunk is locked at a time.
+struct WorldLight<'a, S: ChunkSystem> {
+    chunk_sys: &'a S,
+    // most accesses are in the same chunk as the last one.
+    last_chunk: Option<(IVec3, &'a ChunkPtr)>,
+    // chunks meshed with the changed light.
+    dirty: HashSet<IVec3>,
+}
+
+impl<'a, S: ChunkSystem> WorldLight<'a, S> {
+    fn new(chunk_sys: &'a S) -> Self {
+        Self {
+            chunk_sys,
+            last_chunk: None,
+            dirty: HashSet::default(),
+        }
+    }
+
+    fn chunk(&mut self, chunkpos: IVec3) -> Option<&'a ChunkPtr> {
+        if let Some((cp, chunkptr)) = self.last_chunk {
+            if cp == chunkpos {
+                return Some(chunkptr);
+            }
+        }
+        let chunkptr = self.chunk_sys.get_chunk(chunkpos)?;
+        self.last_chunk = Some((chunkpos, chunkptr));
+        Some(chunkptr)
+    }
+
+    // the chunk of the cell, and the neighbor chunks if the cell is on the boundary (their meshes sample the cell).
+    fn mark_dirty(&mut self, p: IVec3) {
+        let lp = Chunk::as_localpos(p);
+        let range = |a: usize| -((lp[a] == 0) as i32)..=(lp[a] == Chunk::SIZE - 1) as i32;
+        for y in range(1) {
+            for z in range(2) {
+                for x in range(0) {
+                    self.dirty.insert(Chunk::as_chunkpos(p + ivec3(x, y, z)));
+                }
+            }
+        }
+    }
+
+    fn into_dirty(self) -> HashSet<IVec3> {
+        let chunk_sys = self.chunk_sys;
+        let mut dirty = self.dirty;
+        dirty.retain(|cp| chunk_sys.has_chunk(*cp));
+        dirty
+    }
+}
+
+impl<S: ChunkSystem> LightAccess for WorldLight<'_, S> {
+    fn get(&mut self, p: IVec3) -> Option<(Cell, u8)> {
+        let chunk = self.chunk(Chunk::as_chunkpos(p))?.read().unwrap();
+        let lp = Chunk::as_localpos(p);
+        Some((*chunk.get_cell(lp), chunk.get_light(lp)))
+    }
+
+    fn set(&mut self, p: IVec3, light: u8) {
+        let Some(chunkptr) = self.chunk(Chunk::as_chunkpos(p)) else {
+            return;
+        };
+        chunkptr.write().unwrap().set_light(Chunk::as_localpos(p), light);
+        self.mark_dirty(p);
+    }
+}
+
+// Cells of a chunk, in local position. cells out of the chunk are treated as not loaded.
+struct LocalLight<'a>(&'a mut Chunk);
+
+impl LightAccess for LocalLight<'_> {
+    fn get(&mut self, lp: IVec3) -> Option<(Cell, u8)> {
+        Chunk::is_localpos(lp).then(|| (*self.0.get_cell(lp), self.0.get_light(lp)))
+    }
+
+    fn set(&mut self, lp: IVec3, light: u8) {
+        self.0.set_light(lp, light);
+    }
+}
+
+// Spread the light of the queued cells (position, channel). a neighbor takes the level - 1 - its opacity if it's darker.
+// max sky light goes down through clear cells without decreasing.
+fn propagate_increase(access: &mut impl LightAccess, queue: &mut VecDeque<(IVec3, u8)>) {
+    while let Some((p, ch)) = queue.pop_front() {
+        let Some((_, light)) = access.get(p) else {
+            continue;
+        };
+        let level = channel(light, ch);
+        if level == 0 {
+            continue;
+        }
+        for dir in DIRS {
+            let n = p + dir;
+            let Some((c, neib_light)) = access.get(n) else {
+                continue;
+            };
+            let opacity = light_opacity(&c);
+            if opacity >= MAX_LIGHT {
+                continue;
+            }
+            let sky_down = ch == SKY && dir == IVec3::NEG_Y && level == MAX_LIGHT && opacity == 0;
+            let new_level = if sky_down { MAX_LIGHT } else { level.saturating_sub(1 + opacity) };
+            if channel(neib_light, ch) < new_level {
+                access.set(n, with_channel(neib_light, ch, new_level));
+                queue.push_back((n, ch));
+            }
+        }
+    }
+}
+
+// Remove the light came from the queued cells (position, channel, the removed level). they are already set dark.
+// neighbors darker than the removed level were lit by it, they are removed too. brighter neighbors are lit by
+// other sources, they are queued to `increase` to fill the removed area again. sources keep their own light.
+fn propagate_decrease(world: &mut WorldLight<impl ChunkSystem>, queue: &mut VecDeque<(IVec3, u8, u8)>, increase: &mut VecDeque<(IVec3, u8)>) {
+    while let Some((p, ch, level)) = queue.pop_front() {
+        for dir in DIRS {
+            let n = p + dir;
+            let Some((c, neib_light)) = world.get(n) else {
+                continue;
+            };
+            let neib_level = channel(neib_light, ch);
+            if neib_level == 0 {
+                continue;
+            }
+            if neib_level < level || (ch == SKY && dir == IVec3::NEG_Y && level == MAX_LIGHT) {
+                let source = channel(source_light(&c, is_open_sky(world.chunk_sys, n)), ch);
+                world.set(n, with_channel(neib_light, ch, source));
+                queue.push_back((n, ch, neib_level));
+                if source > 0 {
+                    increase.push_back((n, ch));
+                }
+            } else {
+                increase.push_back((n, ch));
+            }
+        }
+    }
+}
+
+/// Light a newly loaded chunk, and spread the light between it and the loaded neighbors.
+/// Returns the loaded chunks to remesh.
+pub fn light_new_chunk(chunk_sys: &impl ChunkSystem, chunkpos: IVec3) -> HashSet<IVec3> {
+    let Some(chunkptr) = chunk_sys.get_chunk(chunkpos) else {
+        return HashSet::default();
+    };
+    let open_sky = !chunk_sys.has_chunk(chunkpos + IVec3::Y * Chunk::SIZE);
+
+    let mut world = WorldLight::new(chunk_sys);
+    let mut increase = VecDeque::new();
+    let mut decrease = VecDeque::new();
+
+    // inside the chunk first, without locking other chunks. then the boundary cells spread to the neighbors.
+    {
+        let mut chunk = chunkptr.write().unwrap();
+        chunk.clear_light();
+
+        let mut local = VecDeque::new();
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            let light = source_light(chunk.get_cell(lp), open_sky && lp.y == Chunk::SIZE - 1);
+            if light != 0 {
+                chunk.set_light(lp, light);
+                local.extend(CHANNELS.map(|ch| (lp, ch)));
+            }
+        });
+        propagate_increase(&mut LocalLight(&mut chunk), &mut local);
+
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            if lp.min_element() == 0 || lp.max_element() == Chunk::SIZE - 1 {
+                let light = chunk.get_light(lp);
+                if light != 0 {
+                    increase.extend(CHANNELS.iter().filter(|ch| channel(light, **ch) != 0).map(|ch| (chunkpos + lp, *ch)));
+                    world.mark_dirty(chunkpos + lp);
+                }
+            }
+        });
+    }
+
+    // the light of the neighbors spreads into the chunk.
+    for dir in DIRS {
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            if !Chunk::is_localpos(lp + dir) {
+                increase.extend(CHANNELS.map(|ch| (chunkpos + lp + dir, ch)));
+            }
+        });
+    }
+
+    // the chunk below was lit by the open sky. now the sky light comes through this chunk.
+    if chunk_sys.has_chunk(chunkpos - IVec3::Y * Chunk::SIZE) {
+        for lz in 0..Chunk::SIZE {
+            for lx in 0..Chunk::SIZE {
+                let p = chunkpos + ivec3(lx, -1, lz);
+                let Some((_, light)) = world.get(p) else {
+                    continue;
+                };
+                if sky_light(light) != 0 {
+                    world.set(p, with_channel(light, SKY, 0));
+                    decrease.push_back((p, SKY, sky_light(light)));
+                }
+            }
+        }
+    }
+
+    propagate_decrease(&mut world, &mut decrease, &mut increase);
+    propagate_increase(&mut world, &mut increase);
+
+    world.dirty.insert(chunkpos);
+    world.into_dirty()
+}
+
+/// Update the light after the cells at `positions` are modified. Returns the loaded chunks to remesh.
+pub fn relight_cells(chunk_sys: &impl ChunkSystem, positions: &[IVec3]) -> HashSet<IVec3> {
+    let mut world = WorldLight::new(chunk_sys);
+    let mut increase = VecDeque::new();
+    let mut decrease = VecDeque::new();
+
+    for &p in positions {
+        let Some((c, light)) = world.get(p) else {
+            continue;
+        };
+        let new_light = source_light(&c, is_open_sky(chunk_sys, p));
+        world.set(p, new_light);
+
+        for ch in CHANNELS {
+            if channel(light, ch) != 0 {
+                decrease.push_back((p, ch, channel(light, ch)));
+            }
+            if channel(new_light, ch) != 0 {
+                increase.push_back((p, ch));
+            }
+        }
+        // the light around spreads into the cell if it's clear now.
+        if light_opacity(&c) < MAX_LIGHT {
+            for dir in DIRS {
+                increase.extend(CHANNELS.map(|ch| (p + dir, ch)));
+            }
+        }
+    }
+
+    propagate_decrease(&mut world, &mut decrease, &mut increase);
+    propagate_increase(&mut world, &mut increase);
+
+    world.into_dirty()
+}
@@ -33,12 +33,21 @@ pub mod mtl {
     pub const WATER: u16 = 24;
     pub const SAND: u16 = 19;
     pub const LOG: u16 = 13;
+    pub const SNOW: u16 = 4;
+    pub const GRAVEL: u16 = 9;
+    pub const DRY_GRASS: u16 = 14;
+    pub const MOSS: u16 = 17;
+    pub const COAL_ORE: u16 = 25;
+    pub const IRON_ORE: u16 = 26;
 
     pub const SHORTGRASS: u16 = 13;
     pub const BUSH: u16 = 14;
     pub const ROSE: u16 = 15;
     pub const FERN: u16 = 16;
     pub const LEAVES: u16 = 23;
+
+    /// number of tex_id, including NIL.
+    pub const COUNT: u16 = 27;
 }
 
 // use crate::util::registry::*;
@@ -48,7 +57,7 @@ pub mod mtl_tex {
     use bevy::math::Vec2;
 
     pub fn map_uv(uv: Vec2, tex_id: u16) -> Vec2 {
-        const TEX_CAP: f32 = 24.;
+        const TEX_CAP: f32 = (super::mtl::COUNT - 1) as f32;
         let tex = tex_id - 1; // -1: offset the 0 Nil
         Vec2::new(uv.x / TEX_CAP + tex as f32 / TEX_CAP, uv.y)
     }
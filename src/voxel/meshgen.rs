@@ -10,7 +10,7 @@ use bevy_egui::egui::emath::inverse_lerp;
 
 use crate::util::iter;
 
-use super::{chunk::*, material::mtl_tex};
+use super::{chunk::*, lighting, material::mtl_tex, VoxModel, VoxModels};
 
 // Temporary Solution. since i want make Vec3 as HashMap's key but glam Vec3 doesn't support trait of Hash, Eq,
 // #[derive(PartialEq)]
@@ -29,6 +29,10 @@ pub struct Vertex {
     pub pos: Vec3,
     pub uv: Vec2,
     pub norm: Vec3,
+    // ambient occlusion, 1: open, less is darker.
+    pub ao: f32,
+    // (sky, block) light levels in [0, 1]. see `lighting`.
+    pub light: Vec2,
 }
 
 impl Hash for Vertex {
@@ -36,6 +40,8 @@ impl Hash for Vertex {
         self.pos.mul(100.).as_ivec3().hash(state);
         self.norm.mul(100.).as_ivec3().hash(state);
         self.uv.mul(100.).as_ivec2().hash(state);
+        ((self.ao * 100.) as i32).hash(state);
+        self.light.mul(100.).as_ivec2().hash(state);
         // self.pos.x.to_bits().hash(state);
         // self.pos.y.to_bits().hash(state);
         // self.pos.z.to_bits().hash(state);
@@ -51,6 +57,8 @@ impl PartialEq for Vertex {
         self.pos.mul(100.).as_ivec3() == other.pos.mul(100.).as_ivec3()
             && self.norm.mul(100.).as_ivec3() == other.norm.mul(100.).as_ivec3()
             && self.uv.mul(100.).as_ivec2() == other.uv.mul(100.).as_ivec2()
+            && (self.ao * 100.) as i32 == (other.ao * 100.) as i32
+            && self.light.mul(100.).as_ivec2() == other.light.mul(100.).as_ivec2()
     }
 }
 
@@ -61,6 +69,9 @@ pub struct VertexBuffer {
     pub vertices: Vec<Vertex>,
 
     pub indices: Vec<u32>,
+
+    // corner of each vertex in its triangles, 0, 1 or 2. set by `compute_indexed`.
+    pub corners: Vec<u8>,
 }
 
 impl VertexBuffer {
@@ -71,7 +82,11 @@ impl VertexBuffer {
     // }
 
     pub fn push_vertex(&mut self, pos: Vec3, uv: Vec2, norm: Vec3) {
-        self.vertices.push(Vertex { pos, uv, norm });
+        self.push_vertex_lit(pos, uv, norm, 1.0, Vec2::ONE);
+    }
+
+    pub fn push_vertex_lit(&mut self, pos: Vec3, uv: Vec2, norm: Vec3, ao: f32, light: Vec2) {
+        self.vertices.push(Vertex { pos, uv, norm, ao, light });
     }
 
     pub fn is_indexed(&self) -> bool {
@@ -101,6 +116,7 @@ impl VertexBuffer {
     pub fn clear(&mut self) {
         self.vertices.clear();
         self.indices.clear();
+        self.corners.clear();
     }
 
     // pub fn compute_flat_normals(&mut self) {
@@ -160,41 +176,39 @@ impl VertexBuffer {
         }
     }
 
-    // pub fn compute_indexed(&mut self) {
-    //     assert!(!self.is_indexed());
-    //     self.indices.clear();
-    //     self.indices.reserve(self.vertex_count());
-
-    //     let mut vert2idx = HashMap::<Vertex, u32>::new();
-
-    //     let mut vertices = Vec::new();
-
-    //     for vert in self.vertices.iter() {
-    //         // if let Some(idx) = vert2idx.get(vert) {
-    //         //     self.indices.push(*idx);
-    //         // } else {
-    //         //     let idx = vertices.len() as u32;
-    //         //     vert2idx.insert(*vert, idx);
-    //         //     vertices.push(*vert);
-    //         //     self.indices.push(idx);
-    //         // }
-
-    //         match vert2idx.entry(*vert) {
-    //             Entry::Occupied(e) => {
-    //                 let idx = *e.get();
-    //                 self.indices.push(idx);
-    //             }
-    //             Entry::Vacant(e) => {
-    //                 let idx = vertices.len() as u32;
-    //                 e.insert(idx);
-    //                 vertices.push(*vert);
-    //                 self.indices.push(idx);
-    //             }
-    //         }
-    //     }
+    /// Deduplicate vertices. the terrain shader blends the 3 materials of a triangle by barycentric weights, which are
+    /// interpolated from the one-hot corner of each vertex. so a vertex is only shared by triangles where it is the same corner,
+    /// the 3 vertices of a triangle are always different corners. (before, the corner was `vertex_index % 3` of non-indexed vertices)
+    pub fn compute_indexed(&mut self) {
+        assert!(!self.is_indexed());
+        const PERMUTATIONS: [[u8; 3]; 6] = [[0, 1, 2], [1, 2, 0], [2, 0, 1], [0, 2, 1], [1, 0, 2], [2, 1, 0]];
 
-    //     self.vertices = vertices;
-    // }
+        let mut vert2idx = HashMap::<(Vertex, u8), u32>::new();
+        let mut vertices = Vec::new();
+        let mut corners = Vec::new();
+        self.indices.clear();
+        self.indices.reserve(self.vertices.len());
+
+        for tri in self.vertices.chunks_exact(3) {
+            // assign corners of the triangle which reuse the most existing vertices.
+            let perm = PERMUTATIONS
+                .iter()
+                .min_by_key(|perm| (0..3).filter(|&i| !vert2idx.contains_key(&(tri[i], perm[i]))).count())
+                .unwrap();
+
+            for i in 0..3 {
+                let idx = *vert2idx.entry((tri[i], perm[i])).or_insert_with(|| {
+                    vertices.push(tri[i]);
+                    corners.push(perm[i]);
+                    (vertices.len() - 1) as u32
+                });
+                self.indices.push(idx);
+            }
+        }
+
+        self.vertices = vertices;
+        self.corners = corners;
+    }
 
     pub fn to_mesh(&self, mesh: &mut Mesh) {
         let pos: Vec<Vec3> = self.vertices.iter().map(|v| v.pos).collect();
@@ -206,6 +220,26 @@ impl VertexBuffer {
         let norm: Vec<Vec3> = self.vertices.iter().map(|v| v.norm).collect();
         mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, norm);
 
+        // light levels for the terrain shader, as the second uv.
+        let light: Vec<Vec2> = self.vertices.iter().map(|v| v.light).collect();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_1, light);
+
+        // one-hot corners and AO for the terrain shader. as vertex colors, it's a standard attribute with a location in the shader.
+        // otherwise (foliage, StandardMaterial) the brightness of the light, multiplied to the base color.
+        let colors: Vec<[f32; 4]> = if !self.corners.is_empty() {
+            self.corners
+                .iter()
+                .zip(self.vertices.iter())
+                .map(|(c, v)| Vec3::AXES[*c as usize].extend(v.ao).to_array())
+                .collect()
+        } else {
+            self.vertices
+                .iter()
+                .map(|v| Vec3::splat(lighting::brightness(v.light.max_element()) * v.ao).extend(1.0).to_array())
+                .collect()
+        };
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+
         if self.is_indexed() {
             mesh.insert_indices(Indices::U32(self.indices.clone()));
         }
@@ -215,9 +249,73 @@ impl VertexBuffer {
 pub struct MeshGen {}
 
 impl MeshGen {
+    /// LOD levels of far chunks. the isovalue field is downsampled by 2^lod, up to 8x.
+    pub const MAX_LOD: u8 = 3;
+
     pub fn generate_chunk_mesh(vbuf: &mut VertexBuffer, chunk: &Chunk) {
-        Self::sn_contouring(vbuf, chunk);
+        Self::sn_contouring(vbuf, chunk, 0);
+
+        Self::put_cubes_greedy(vbuf, chunk);
+
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            let c = chunk.get_cell(lp);
+            if c.tex_id == 0 {
+                return;
+            }
+            if let Some((min, max)) = shape_box(c.shape_id).filter(|_| c.shape_id != VoxShape::Cube) {
+                put_box(vbuf, chunk, lp, min, max, c.tex_id, [false; 6]);
+            } else if c.shape_id == VoxShape::Fence {
+                put_fence(vbuf, chunk, lp, c.tex_id);
+            }
+        });
+    }
+
+    /// Far chunks. the isosurface of the downsampled isovalue field, with skirts hiding the cracks between chunks of different LOD.
+    /// Slabs, fences, foliage and models are skipped. Normals are computed here since skirts take the normals of the surface.
+    pub fn generate_chunk_mesh_lod(vbuf: &mut VertexBuffer, chunk: &Chunk, lod: u8) {
+        Self::sn_contouring(vbuf, chunk, lod);
+        vbuf.compute_smooth_normals();
+        Self::put_skirts(vbuf, (1 << lod) as f32);
 
+        // cubes stay full resolution, they are cheap after merging. e.g. structures are still visible from far.
+        Self::put_cubes_greedy(vbuf, chunk);
+    }
+
+    // Skirts. edges used by only one triangle are on the border of the surface (the chunk boundary),
+    // they are extruded into the solid by `depth`, covering the gap to the neighbor's surface of another LOD.
+    fn put_skirts(vbuf: &mut VertexBuffer, depth: f32) {
+        let key = |p: Vec3| p.mul(100.).as_ivec3().to_array(); // as same as Vertex's Hash
+        let edge_key = |a: Vec3, b: Vec3| {
+            let (a, b) = (key(a), key(b));
+            (a.min(b), a.max(b))
+        };
+
+        let mut edges = HashMap::<([i32; 3], [i32; 3]), u32>::new();
+        for tri in vbuf.vertices.chunks_exact(3) {
+            for i in 0..3 {
+                *edges.entry(edge_key(tri[i].pos, tri[(i + 1) % 3].pos)).or_default() += 1;
+            }
+        }
+
+        let mut skirts = Vec::new();
+        for tri in vbuf.vertices.chunks_exact(3) {
+            for i in 0..3 {
+                let (a, b) = (tri[i], tri[(i + 1) % 3]);
+                if edges.get(&edge_key(a.pos, b.pos)) != Some(&1) {
+                    continue;
+                }
+                let (mut a1, mut b1) = (a, b);
+                a1.pos -= a.norm * depth;
+                b1.pos -= b.norm * depth;
+                // reversed edge b -> a, continues the triangle over the border.
+                skirts.extend([b, a, a1, b, a1, b1]);
+            }
+        }
+        vbuf.vertices.extend(skirts);
+    }
+
+    /// Cube cells, a quad per visible face. the reference of `put_cubes_greedy`.
+    pub fn put_cubes_naive(vbuf: &mut VertexBuffer, chunk: &Chunk) {
         for ly in 0..Chunk::SIZE {
             for lz in 0..Chunk::SIZE {
                 for lx in 0..Chunk::SIZE {
@@ -233,17 +331,133 @@ impl MeshGen {
         }
     }
 
-    pub fn generate_chunk_mesh_foliage(vbuf: &mut VertexBuffer, chunk: &Chunk) {
+    /// Cube cells, Greedy Meshing. visible faces in a slice are merged into maximal rectangles of the same texture.
+    /// Looks the same as `put_cubes_naive` since cube textures are mapped by world position, not by the quad uv.
+    /// Only faces with the same AO on all 4 corners are merged, others would lose the AO of inner corners.
+    /// Faces are lit by the cell in front of them, merged faces have the same light.
+    pub fn put_cubes_greedy(vbuf: &mut VertexBuffer, chunk: &Chunk) {
+        const N: usize = Chunk::SIZE as usize;
+        // tex_id | packed AO << 16 | light << 24 of visible faces. 0 if invisible.
+        let mut mask = [0u32; N * N];
+
+        for face_i in 0..6 {
+            // the slice is on axis u, v. normal to the face axis.
+            let axis = face_i / 2;
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+
+            for d in 0..Chunk::SIZE {
+                for (i, m) in mask.iter_mut().enumerate() {
+                    let mut lp = IVec3::ZERO;
+                    lp[axis] = d;
+                    lp[u] = (i % N) as i32;
+                    lp[v] = (i / N) as i32;
+
+                    let c = chunk.get_cell(lp);
+                    *m = if c.is_obaque_cube() && !face_occluded(chunk, lp, face_i, Vec2::ZERO, Vec2::ONE) {
+                        let light = chunk.get_light_rel(lp + face_dir(face_i));
+                        c.tex_id as u32 | (pack_ao(cube_face_ao(chunk, lp, face_i)) as u32) << 16 | (light as u32) << 24
+                    } else {
+                        0
+                    };
+                }
+
+                for j in 0..N {
+                    let mut i = 0;
+                    while i < N {
+                        let m = mask[j * N + i];
+                        if m == 0 {
+                            i += 1;
+                            continue;
+                        }
+                        let (tex_id, ao, light) = (m as u16, unpack_ao((m >> 16) as u8), (m >> 24) as u8);
+                        let mergeable = ao.iter().all(|l| *l == ao[0]);
+
+                        // grow along u, then along v while the whole row matches.
+                        let mut w = 1;
+                        while mergeable && i + w < N && mask[j * N + i + w] == m {
+                            w += 1;
+                        }
+                        let mut h = 1;
+                        while mergeable && j + h < N && mask[(j + h) * N + i..(j + h) * N + i + w].iter().all(|n| *n == m) {
+                            h += 1;
+                        }
+                        for row in j..j + h {
+                            mask[row * N + i..row * N + i + w].fill(0);
+                        }
+
+                        let mut min = IVec3::ZERO;
+                        min[axis] = d;
+                        min[u] = i as i32;
+                        min[v] = j as i32;
+                        let mut size = IVec3::ONE;
+                        size[u] = w as i32;
+                        size[v] = h as i32;
+                        put_cube_face(vbuf, face_i, min.as_vec3(), size.as_vec3(), tex_id, ao, light);
+
+                        i += w;
+                    }
+                }
+            }
+        }
+    }
+
+    // Foliage and CustomMesh cells. models keep their own normals and uv, so they are in the foliage mesh (no collision).
+    pub fn generate_chunk_mesh_foliage(vbuf: &mut VertexBuffer, chunk: &Chunk, models: &VoxModels) {
         iter::iter_xzy(Chunk::SIZE, |lp| {
             let c = chunk.get_cell(lp);
+            let first_vert = vbuf.vertices.len();
 
             if c.tex_id != 0 {
                 if c.shape_id == VoxShape::Leaves {
                     put_leaves(vbuf, lp.as_vec3(), c.tex_id);
                 } else if c.shape_id == VoxShape::Grass {
                     put_grass(vbuf, lp.as_vec3(), c.tex_id);
+                } else if let VoxShape::CustomMesh { mesh_id, rotation } = c.shape_id {
+                    if let Some(model) = models.get(mesh_id) {
+                        put_model(vbuf, lp.as_vec3(), model, rotation, c.tex_id);
+                    }
                 }
             }
+
+            // lit by the cell itself.
+            let light = lighting::light_levels(chunk.get_light(lp));
+            vbuf.vertices[first_vert..].iter_mut().for_each(|v| v.light = light);
+        });
+    }
+
+    /// Fluid cells, in a separate mesh with a transparent material. the top is flat at the fluid level, faces between
+    /// fluid cells (except the step to a lower level) and against solid cells are culled, the terrain surface covers them.
+    pub fn generate_chunk_mesh_fluid(vbuf: &mut VertexBuffer, chunk: &Chunk) {
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            let c = chunk.get_cell(lp);
+            if !c.is_fluid() {
+                return;
+            }
+            let height = fluid_height(chunk, lp);
+            let light = chunk.get_light(lp);
+
+            for face_i in 0..6 {
+                // not loaded, the face would be a wall on the border of the loaded area.
+                let neib_p = lp + face_dir(face_i);
+                let Some(neib) = chunk.get_cell_neighbor(neib_p) else {
+                    continue;
+                };
+                let (y0, y1) = match face_i {
+                    3 if neib.is_fluid() || (is_occluder(&neib) && height >= 1.0) => continue,
+                    2 if neib.is_fluid() || is_occluder(&neib) => continue,
+                    2 | 3 => (0.0, height),
+                    _ if is_occluder(&neib) => continue,
+                    _ => {
+                        let neib_height = fluid_height(chunk, neib_p);
+                        if neib_height >= height {
+                            continue;
+                        }
+                        (neib_height, height)
+                    }
+                };
+                let min = lp.as_vec3() + vec3(0.0, y0, 0.0);
+                put_cube_face(vbuf, face_i, min, vec3(1.0, y1 - y0, 1.0), c.tex_id, [3; 4], light);
+            }
         });
     }
 
@@ -317,7 +531,7 @@ impl MeshGen {
 
     // Naive SurfaceNets Method of Evaluate FeaturePoint.
     // return in-cell point.
-    fn sn_featurepoint(lp: IVec3, chunk: &Chunk) -> Vec3 {
+    fn sn_featurepoint(lp: IVec3, field: &SampledField) -> Vec3 {
         let mut sign_changes = 0;
         let mut fp_sum = Vec3::ZERO;
 
@@ -325,8 +539,8 @@ impl MeshGen {
             let edge = Self::EDGE[edge_i];
             let v0 = Self::VERT[edge[0]];
             let v1 = Self::VERT[edge[1]];
-            let c0 = chunk.get_cell_rel(lp + v0);
-            let c1 = chunk.get_cell_rel(lp + v1);
+            let c0 = field.get(lp + v0);
+            let c1 = field.get(lp + v1);
 
             if Self::sn_signchanged(&c0, &c1) {
                 if let Some(t) = inverse_lerp(c0.isovalue()..=c1.isovalue(), 0.0) {
@@ -355,13 +569,13 @@ impl MeshGen {
 
     // Evaluate Normal of a Cell FeaturePoint
     // via Approxiate Differental Gradient
-    fn sn_grad(lp: IVec3, chunk: &Chunk) -> Vec3 {
+    fn sn_grad(lp: IVec3, field: &SampledField) -> Vec3 {
         // let E = 1;  // Epsilon
-        let val = chunk.get_cell_rel(lp).isovalue();
+        let val = field.get(lp).isovalue();
         vec3(
-            chunk.get_cell_rel(lp + IVec3::X).isovalue() - val,
-            chunk.get_cell_rel(lp + IVec3::Y).isovalue() - val,
-            chunk.get_cell_rel(lp + IVec3::Z).isovalue() - val,
+            field.get(lp + IVec3::X).isovalue() - val,
+            field.get(lp + IVec3::Y).isovalue() - val,
+            field.get(lp + IVec3::Z).isovalue() - val,
             // chunk.get_cell_rel(lp + IVec3::X).value - chunk.get_cell_rel(lp - IVec3::X).value,
             // chunk.get_cell_rel(lp + IVec3::Y).value - chunk.get_cell_rel(lp - IVec3::Y).value,
             // chunk.get_cell_rel(lp + IVec3::Z).value - chunk.get_cell_rel(lp - IVec3::Z).value,
@@ -371,16 +585,56 @@ impl MeshGen {
         .unwrap_or(Vec3::NEG_Y) // NEG_Y will be Y after grad-to-normal flip.
     }
 
-    fn sn_contouring(vbuf: &mut VertexBuffer, chunk: &Chunk) {
-        for ly in 0..Chunk::SIZE {
-            for lz in 0..Chunk::SIZE {
-                for lx in 0..Chunk::SIZE {
+    // AO of the FeaturePoint in cell lp, by the solid fraction of the 4x4x4 cells around.
+    // it's about a half on a flat surface, more in creases and caves.
+    fn sn_ao(lp: IVec3, field: &SampledField) -> f32 {
+        let mut solid = 0;
+        // -1..=2, centered at the FeaturePoint in [lp, lp+1].
+        for y in -1..=2 {
+            for z in -1..=2 {
+                for x in -1..=2 {
+                    if is_occluder(&field.get(lp + IVec3::new(x, y, z))) {
+                        solid += 1;
+                    }
+                }
+            }
+        }
+        let occlusion = ((solid as f32 / 64.0 - 0.5) * 2.0).clamp(0.0, 1.0);
+        1.0 - occlusion * (1.0 - AO_LEVELS[0])
+    }
+
+    // Light of the FeaturePoint in cell lp, averaged over the clear cells of the 8 cells around.
+    fn sn_light(lp: IVec3, field: &SampledField) -> Vec2 {
+        let mut sum = Vec2::ZERO;
+        let mut n = 0;
+        for vert in Self::VERT {
+            let p = lp + vert;
+            if lighting::light_opacity(&field.get(p)) < lighting::MAX_LIGHT {
+                sum += lighting::light_levels(field.get_light(p));
+                n += 1;
+            }
+        }
+        if n == 0 {
+            Vec2::ZERO
+        } else {
+            sum / n as f32
+        }
+    }
+
+    // SurfaceNets of the isovalue field sampled every 2^lod cells. positions in the field are scaled back to the chunk.
+    fn sn_contouring(vbuf: &mut VertexBuffer, chunk: &Chunk, lod: u8) {
+        let field = SampledField::new(chunk, 1 << lod);
+        let scale = field.scale as f32;
+
+        for ly in 0..field.n {
+            for lz in 0..field.n {
+                for lx in 0..field.n {
                     let lp = IVec3::new(lx, ly, lz);
-                    let c0 = chunk.get_cell(lp);
+                    let c0 = &field.get(lp);
 
                     // for 3 axes edges, if sign-changed, connect adjacent 4 cells' vertices
                     for axis_i in 0..3 {
-                        let c1 = match chunk.get_cell_neighbor(lp + Self::AXES[axis_i]) {
+                        let c1 = match field.get_neighbor(lp + Self::AXES[axis_i]) {
                             None => continue, // do not generate face if it's a Nil Cell (non-loaded)
                             Some(c1) => c1,
                         };
@@ -394,15 +648,17 @@ impl MeshGen {
                             let winded_vi = if winding_flip { 5 - quadvert_i } else { quadvert_i };
 
                             let p = lp + Self::ADJACENT[axis_i][winded_vi];
-                            let c = chunk.get_cell_rel(p);
+                            let c = field.get(p);
 
-                            let fp = Self::sn_featurepoint(p, chunk);
-                            let norm = -Self::sn_grad(p, chunk);
+                            let fp = Self::sn_featurepoint(p, &field);
+                            let norm = -Self::sn_grad(p, &field);
+                            let ao = Self::sn_ao(p, &field);
+                            let light = Self::sn_light(p, &field);
 
                             let mut nearest_val = f32::INFINITY;
                             let mut nearest_tex = c.tex_id;
                             for vert in Self::VERT {
-                                let c = chunk.get_cell_rel(p + vert);
+                                let c = field.get(p + vert);
                                 if !c.is_isoval_empty() && c.isovalue() < nearest_val {
                                     nearest_val = c.isovalue();
                                     nearest_tex = c.tex_id;
@@ -410,7 +666,8 @@ impl MeshGen {
                                 }
                             }
 
-                            vbuf.push_vertex(p.as_vec3() + fp + 0.5, vec2(nearest_tex as f32, -1.), norm);
+                            // +0.5: the isovalue of a cell is at its center.
+                            vbuf.push_vertex_lit((p.as_vec3() + fp) * scale + 0.5, vec2(nearest_tex as f32, -1.), norm, ao, light);
                         }
                     }
                 }
@@ -419,6 +676,55 @@ impl MeshGen {
     }
 }
 
+// Cells of a chunk sampled every `scale` cells, for LOD. samples are -2..=n+1 on each axis, the ones out of the chunk
+// are from neighbor chunks, None if the neighbor is not loaded. copied once, neighbor chunks are locked only here.
+struct SampledField {
+    n: i32,
+    scale: i32,
+    cells: Vec<Option<Cell>>,
+    lights: Vec<u8>,
+}
+
+impl SampledField {
+    fn new(chunk: &Chunk, scale: i32) -> Self {
+        let n = Chunk::SIZE / scale;
+        let size = n + 4;
+        let mut cells = Vec::with_capacity((size * size * size) as usize);
+        let mut lights = Vec::with_capacity((size * size * size) as usize);
+        for y in -2..=n + 1 {
+            for z in -2..=n + 1 {
+                for x in -2..=n + 1 {
+                    let p = IVec3::new(x, y, z) * scale;
+                    cells.push(chunk.get_cell_neighbor(p));
+                    lights.push(chunk.get_light_rel(p));
+                }
+            }
+        }
+        Self { n, scale, cells, lights }
+    }
+
+    fn idx(&self, p: IVec3) -> Option<usize> {
+        let size = self.n + 4;
+        let i = p + 2;
+        if i.min_element() < 0 || i.max_element() >= size {
+            return None;
+        }
+        Some(((i.y * size + i.z) * size + i.x) as usize)
+    }
+
+    fn get_neighbor(&self, p: IVec3) -> Option<Cell> {
+        self.cells[self.idx(p)?]
+    }
+
+    fn get_light(&self, p: IVec3) -> u8 {
+        self.idx(p).map_or(0, |i| self.lights[i])
+    }
+
+    fn get(&self, p: IVec3) -> Cell {
+        self.get_neighbor(p).unwrap_or_default()
+    }
+}
+
 #[rustfmt::skip]
 static CUBE_POS: [f32; 6 * 6 * 3] = [
     0., 0., 1., 0., 1., 1., 0., 1., 0.,   0., 0., 1., 0., 1., 0., 0., 0., 0., // Left -X
@@ -452,24 +758,179 @@ static CUBE_NORM: [f32; 6 * 6 * 3] = [
 // static CUBE_IDX: [u32;6*6] = [
 // ];
 
-fn put_cube(vbuf: &mut VertexBuffer, lp: IVec3, chunk: &Chunk, tex_id: u16) {
-    for face_i in 0..6 {
-        let face_dir = Vec3::from_slice(&CUBE_NORM[face_i * 18..]); // 18: 3 scalar * 3 vertex * 2 triangle
+// Box of Cube and Slab shapes in the cell, min and max in [0, 1].
+fn shape_box(shape: VoxShape) -> Option<(Vec3, Vec3)> {
+    Some(match shape {
+        VoxShape::Cube => (Vec3::ZERO, Vec3::ONE),
+        VoxShape::SlabYMin => (Vec3::ZERO, vec3(1.0, 0.5, 1.0)),
+        VoxShape::SlabYMax => (vec3(0.0, 0.5, 0.0), Vec3::ONE),
+        VoxShape::SlabXMin => (Vec3::ZERO, vec3(0.5, 1.0, 1.0)),
+        VoxShape::SlabXMax => (vec3(0.5, 0.0, 0.0), Vec3::ONE),
+        VoxShape::SlabZMin => (Vec3::ZERO, vec3(1.0, 1.0, 0.5)),
+        VoxShape::SlabZMax => (vec3(0.0, 0.0, 0.5), Vec3::ONE),
+        _ => return None,
+    })
+}
+
+// axes of the face plane of CUBE_POS faces. -X, +X, -Y, +Y, -Z, +Z
+fn face_axes(face_i: usize) -> (usize, usize, usize) {
+    let axis = face_i / 2;
+    (axis, (axis + 1) % 3, (axis + 2) % 3)
+}
+
+// The rect the cell covers on its face of the cell boundary, in the face plane axes. None if it doesn't touch the face.
+fn face_rect(c: &Cell, face_i: usize) -> Option<(Vec2, Vec2)> {
+    if c.is_tex_empty() {
+        return None;
+    }
+    let (min, max) = shape_box(c.shape_id)?;
+    let (axis, u, v) = face_axes(face_i);
+    let touches = if face_i & 1 == 0 { min[axis] == 0.0 } else { max[axis] == 1.0 };
+    touches.then_some((vec2(min[u], min[v]), vec2(max[u], max[v])))
+}
 
-        // skip the face if there's Obaque
-        if chunk.get_cell_rel(lp + face_dir.as_ivec3()).is_obaque_cube() {
+fn face_dir(face_i: usize) -> IVec3 {
+    Vec3::from_slice(&CUBE_NORM[face_i * 18..]).as_ivec3() // 18: 3 scalar * 3 vertex * 2 triangle
+}
+
+// brightness of AO levels. 0: the corner is fully occluded, 3: open.
+const AO_LEVELS: [f32; 4] = [0.4, 0.6, 0.8, 1.0];
+
+// Height of the fluid surface in the cell lp, full if there is fluid above. 0 if it's not a fluid.
+fn fluid_height(chunk: &Chunk, lp: IVec3) -> f32 {
+    let c = chunk.get_cell_rel(lp);
+    if !c.is_fluid() {
+        0.0
+    } else if chunk.get_cell_rel(lp + IVec3::Y).is_fluid() {
+        1.0
+    } else {
+        c.fluid_level() as f32 / Cell::FLUID_LEVELS as f32
+    }
+}
+
+// cells occluding light. solid terrain and opaque cubes.
+fn is_occluder(c: &Cell) -> bool {
+    c.is_obaque_cube() || !c.is_isoval_empty()
+}
+
+// AO levels of the 4 corners of a cube face, by the 2 side and 1 diagonal cells in front of each corner.
+// corners are indexed `u | v << 1` where u, v are 0 or 1 on the face plane axes.
+fn cube_face_ao(chunk: &Chunk, lp: IVec3, face_i: usize) -> [u8; 4] {
+    let (_, u, v) = face_axes(face_i);
+    let front = lp + face_dir(face_i);
+    let mut ao = [3; 4];
+    for (corner, level) in ao.iter_mut().enumerate() {
+        let (mut du, mut dv) = (IVec3::ZERO, IVec3::ZERO);
+        du[u] = if corner & 1 == 0 { -1 } else { 1 };
+        dv[v] = if corner & 2 == 0 { -1 } else { 1 };
+        let side1 = is_occluder(&chunk.get_cell_rel(front + du));
+        let side2 = is_occluder(&chunk.get_cell_rel(front + dv));
+        let diag = is_occluder(&chunk.get_cell_rel(front + du + dv));
+        // both sides occlude the corner, regardless of the diagonal.
+        *level = match (side1, side2) {
+            (true, true) => 0,
+            _ => 3 - (side1 as u8 + side2 as u8 + diag as u8),
+        };
+    }
+    ao
+}
+
+fn pack_ao(ao: [u8; 4]) -> u8 {
+    ao[0] | ao[1] << 2 | ao[2] << 4 | ao[3] << 6
+}
+
+fn unpack_ao(packed: u8) -> [u8; 4] {
+    [packed & 3, packed >> 2 & 3, packed >> 4 & 3, packed >> 6 & 3]
+}
+
+// Is the rect on the face of cell lp fully covered by the neighbor cell. e.g. a slab beside a slab of the same orientation.
+fn face_occluded(chunk: &Chunk, lp: IVec3, face_i: usize, min: Vec2, max: Vec2) -> bool {
+    let neib = chunk.get_cell_rel(lp + face_dir(face_i));
+    face_rect(&neib, face_i ^ 1).is_some_and(|(nmin, nmax)| nmin.cmple(min).all() && nmax.cmpge(max).all())
+}
+
+// put a box in the cell lp. faces on the cell boundary are culled if occluded by the neighbor. `joined` faces are skipped.
+fn put_box(vbuf: &mut VertexBuffer, chunk: &Chunk, lp: IVec3, min: Vec3, max: Vec3, tex_id: u16, joined: [bool; 6]) {
+    for (face_i, joined) in joined.into_iter().enumerate() {
+        if joined {
+            continue;
+        }
+        let (axis, u, v) = face_axes(face_i);
+        let on_boundary = if face_i & 1 == 0 { min[axis] == 0.0 } else { max[axis] == 1.0 };
+        if on_boundary && face_occluded(chunk, lp, face_i, vec2(min[u], min[v]), vec2(max[u], max[v])) {
             continue;
         }
+        put_cube_face(vbuf, face_i, lp.as_vec3() + min, max - min, tex_id, [3; 4], chunk.get_light(lp));
+    }
+}
 
-        for vert_i in 0..6 {
-            // let uv = Vec2::from_slice(&CUBE_UV[face_i * 12 + vert_i * 2..]);
+// Fence. a post, connected by rails to fences and opaque cubes beside it.
+fn put_fence(vbuf: &mut VertexBuffer, chunk: &Chunk, lp: IVec3, tex_id: u16) {
+    const POST: f32 = 0.125; // half width
+    const RAIL: f32 = 0.0625;
+    const RAIL_Y: [(f32, f32); 2] = [(0.375, 0.5625), (0.75, 0.9375)];
+
+    let is_fence = |c: &Cell| c.shape_id == VoxShape::Fence && !c.is_tex_empty();
+    let joined_y = [is_fence(&chunk.get_cell_rel(lp - IVec3::Y)), is_fence(&chunk.get_cell_rel(lp + IVec3::Y))];
+    put_box(
+        vbuf,
+        chunk,
+        lp,
+        vec3(0.5 - POST, 0.0, 0.5 - POST),
+        vec3(0.5 + POST, 1.0, 0.5 + POST),
+        tex_id,
+        [false, false, joined_y[0], joined_y[1], false, false],
+    );
+
+    // -X, +X, -Z, +Z
+    for face_i in [0, 1, 4, 5] {
+        let neib = chunk.get_cell_rel(lp + face_dir(face_i));
+        if !is_fence(&neib) && !neib.is_obaque_cube() {
+            continue;
+        }
+        let (axis, _, _) = face_axes(face_i);
+        // across the axis, from the post to the cell boundary.
+        let (from, to) = if face_i & 1 == 0 { (0.0, 0.5 - POST) } else { (0.5 + POST, 1.0) };
+        let mut joined = [false; 6];
+        // the end toward a neighbor fence meets its rail.
+        joined[face_i] = is_fence(&neib);
+        joined[face_i ^ 1] = true; // inside the post
+
+        for (y0, y1) in RAIL_Y {
+            let mut min = vec3(0.5 - RAIL, y0, 0.5 - RAIL);
+            let mut max = vec3(0.5 + RAIL, y1, 0.5 + RAIL);
+            min[axis] = from;
+            max[axis] = to;
+            put_box(vbuf, chunk, lp, min, max, tex_id, joined);
+        }
+    }
+}
 
-            vbuf.push_vertex(
-                Vec3::from_slice(&CUBE_POS[face_i * 18 + vert_i * 3..]) + lp.as_vec3(),
-                Vec2::new(tex_id as f32, -1.),
-                Vec3::from_slice(&CUBE_NORM[face_i * 18 + vert_i * 3..]),
-            );
+fn put_cube(vbuf: &mut VertexBuffer, lp: IVec3, chunk: &Chunk, tex_id: u16) {
+    for face_i in 0..6 {
+        // skip the face if it's occluded
+        if face_occluded(chunk, lp, face_i, Vec2::ZERO, Vec2::ONE) {
+            continue;
         }
+
+        let light = chunk.get_light_rel(lp + face_dir(face_i));
+        put_cube_face(vbuf, face_i, lp.as_vec3(), Vec3::ONE, tex_id, cube_face_ao(chunk, lp, face_i), light);
+    }
+}
+
+// put a face of the box. for merged cube faces. `ao` levels of the corners, see `cube_face_ao`. `light` of the face.
+fn put_cube_face(vbuf: &mut VertexBuffer, face_i: usize, min: Vec3, size: Vec3, tex_id: u16, ao: [u8; 4], light: u8) {
+    let (_, u, v) = face_axes(face_i);
+    for vert_i in 0..6 {
+        let p = Vec3::from_slice(&CUBE_POS[face_i * 18 + vert_i * 3..]);
+        let corner = p[u] as usize | (p[v] as usize) << 1;
+        vbuf.push_vertex_lit(
+            p * size + min,
+            Vec2::new(tex_id as f32, -1.),
+            Vec3::from_slice(&CUBE_NORM[face_i * 18 + vert_i * 3..]),
+            AO_LEVELS[ao[corner] as usize],
+            lighting::light_levels(light),
+        );
     }
 }
 
@@ -510,6 +971,203 @@ fn put_grass(vbuf: &mut VertexBuffer, pos: Vec3, tex_id: u16) {
     put_face(vbuf, tex_id, pos + 0.5, Quat::from_axis_angle(Vec3::Y, ang * 3.), Vec2::ONE * siz);
 }
 
+// put a model in the cell pos, turned `rotation` * 90 degrees around Y. a turn maps (x, z) to (-z, x).
+fn put_model(vbuf: &mut VertexBuffer, pos: Vec3, model: &VoxModel, rotation: u8, tex_id: u16) {
+    let rot = Quat::from_rotation_y(-PI / 2. * (rotation % 4) as f32);
+    let origin = pos + vec3(0.5, 0.0, 0.5);
+
+    for v in model.vertices.iter() {
+        vbuf.push_vertex(rot * v.pos + origin, mtl_tex::map_uv(v.uv, tex_id), rot * v.norm);
+    }
+}
+
 // fn mat_model(pos: Vec3, rot: Mat3, scale: Vec3) {
 
 // }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+    use crate::voxel::{mtl, ChunkPtr};
+
+    fn cube(tex_id: u16) -> Cell {
+        Cell::new(tex_id, VoxShape::Cube, 0.0)
+    }
+
+    // area covered by the mesh, per face normal and texture.
+    fn face_areas(vbuf: &VertexBuffer) -> HashMap<(IVec3, u16), f32> {
+        let mut areas = HashMap::default();
+        for tri in vbuf.vertices.chunks_exact(3) {
+            let area = (tri[1].pos - tri[0].pos).cross(tri[2].pos - tri[0].pos).length() * 0.5;
+            *areas.entry((tri[0].norm.as_ivec3(), tri[0].uv.x as u16)).or_insert(0.0) += area;
+        }
+        areas
+    }
+
+    // greedy and naive meshes cover the same area. returns the (naive, greedy) meshes.
+    fn assert_same_area(chunk: &Chunk) -> (VertexBuffer, VertexBuffer) {
+        let mut naive = VertexBuffer::default();
+        MeshGen::put_cubes_naive(&mut naive, chunk);
+        let mut greedy = VertexBuffer::default();
+        MeshGen::put_cubes_greedy(&mut greedy, chunk);
+
+        let (naive_areas, greedy_areas) = (face_areas(&naive), face_areas(&greedy));
+        assert_eq!(naive_areas.len(), greedy_areas.len(), "naive {naive_areas:?}, greedy {greedy_areas:?}");
+        for (key, area) in naive_areas.iter() {
+            let greedy_area = greedy_areas.get(key).copied().unwrap_or(0.0);
+            assert!((area - greedy_area).abs() < 1e-3, "face {key:?}: naive {area}, greedy {greedy_area}");
+        }
+        assert!(greedy.vertices.len() <= naive.vertices.len());
+        (naive, greedy)
+    }
+
+    #[test]
+    fn greedy_single_cube() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        chunk.set_cell(ivec3(5, 5, 5), &cube(mtl::STONE));
+
+        let (naive, greedy) = assert_same_area(&chunk);
+        assert_eq!(naive.vertices.len(), 6 * 6);
+        assert_eq!(greedy.vertices.len(), 6 * 6);
+        for face_i in 0..6 {
+            assert_eq!(face_areas(&greedy)[&(face_dir(face_i), mtl::STONE)], 1.0);
+        }
+    }
+
+    #[test]
+    fn greedy_slab() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            if lp.y == 4 {
+                chunk.set_cell(lp, &cube(mtl::STONE));
+            }
+        });
+
+        let (naive, greedy) = assert_same_area(&chunk);
+        assert_eq!(naive.vertices.len(), (16 * 16 * 2 + 16 * 4) * 6);
+        // a quad per face of the slab.
+        assert_eq!(greedy.vertices.len(), 6 * 6);
+        assert_eq!(face_areas(&greedy)[&(IVec3::Y, mtl::STONE)], 256.0);
+    }
+
+    #[test]
+    fn greedy_checkerboard() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            if (lp.x + lp.y + lp.z) % 2 == 0 {
+                chunk.set_cell(lp, &cube(mtl::STONE));
+            }
+        });
+
+        // no faces are adjacent in a slice, nothing to merge.
+        let (naive, greedy) = assert_same_area(&chunk);
+        assert_eq!(greedy.vertices.len(), naive.vertices.len());
+    }
+
+    #[test]
+    fn greedy_mixed_textures() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            if lp.y < 4 {
+                let tex_id = [mtl::STONE, mtl::DIRT, mtl::SAND][((lp.x / 3 + lp.z / 5 + lp.y) % 3) as usize];
+                chunk.set_cell(lp, &cube(tex_id));
+            }
+        });
+
+        let (_, greedy) = assert_same_area(&chunk);
+        let top: f32 = [mtl::STONE, mtl::DIRT, mtl::SAND]
+            .iter()
+            .map(|tex_id| face_areas(&greedy)[&(IVec3::Y, *tex_id)])
+            .sum();
+        assert_eq!(top, 256.0);
+    }
+
+    #[test]
+    fn greedy_chunk_border() {
+        // cubes on the -X and +X borders. the +X neighbor chunk is solid and hides the +X faces.
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        let mut neighbor = Chunk::new(IVec3::X * Chunk::SIZE);
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            neighbor.set_cell(lp, &cube(mtl::DIRT));
+            if lp.y < 8 && (lp.x == 0 || lp.x == Chunk::SIZE - 1) {
+                chunk.set_cell(lp, &cube(mtl::STONE));
+            }
+        });
+        let neighbor: ChunkPtr = Arc::new(RwLock::new(neighbor));
+        chunk.neighbor_chunks[1] = Some(Arc::downgrade(&neighbor));
+
+        let (_, greedy) = assert_same_area(&chunk);
+        let areas = face_areas(&greedy);
+        assert_eq!(areas[&(IVec3::NEG_X, mtl::STONE)], 16.0 * 8.0 * 2.0);
+        assert_eq!(areas[&(IVec3::X, mtl::STONE)], 16.0 * 8.0);
+        assert_eq!(areas[&(IVec3::Y, mtl::STONE)], 16.0 * 2.0);
+    }
+
+    const TOP: usize = 3; // +Y face
+
+    #[test]
+    fn cube_ao_open_face() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        chunk.set_cell(ivec3(5, 5, 5), &cube(mtl::STONE));
+        for face_i in 0..6 {
+            assert_eq!(cube_face_ao(&chunk, ivec3(5, 5, 5), face_i), [3; 4]);
+        }
+    }
+
+    #[test]
+    fn cube_ao_corners() {
+        // corners of the top face are indexed `z | x << 1`, corner 0 is at -X -Z.
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        chunk.set_cell(ivec3(5, 5, 5), &cube(mtl::STONE));
+
+        // one side and the diagonal.
+        chunk.set_cell(ivec3(4, 6, 5), &cube(mtl::STONE));
+        chunk.set_cell(ivec3(4, 6, 4), &cube(mtl::STONE));
+        assert_eq!(cube_face_ao(&chunk, ivec3(5, 5, 5), TOP), [1, 2, 3, 3]);
+
+        // two sides, darkest regardless of the diagonal.
+        chunk.set_cell(ivec3(4, 6, 4), &Cell::default());
+        chunk.set_cell(ivec3(5, 6, 4), &cube(mtl::STONE));
+        assert_eq!(cube_face_ao(&chunk, ivec3(5, 5, 5), TOP), [0, 2, 2, 3]);
+    }
+
+    #[test]
+    fn cube_ao_chunk_border() {
+        // the -X side of the corner is in the neighbor chunk, read by get_cell_rel.
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        chunk.set_cell(ivec3(0, 5, 5), &cube(mtl::STONE));
+        chunk.set_cell(ivec3(0, 6, 4), &cube(mtl::STONE));
+        assert_eq!(cube_face_ao(&chunk, ivec3(0, 5, 5), TOP)[0], 2);
+
+        let mut neighbor = Chunk::new(IVec3::NEG_X * Chunk::SIZE);
+        neighbor.set_cell(ivec3(Chunk::SIZE - 1, 6, 5), &cube(mtl::STONE));
+        let neighbor: ChunkPtr = Arc::new(RwLock::new(neighbor));
+        chunk.neighbor_chunks[0] = Some(Arc::downgrade(&neighbor));
+        assert_eq!(cube_face_ao(&chunk, ivec3(0, 5, 5), TOP)[0], 0);
+    }
+
+    // isosurface terrain, solid below y=8. and x<8 too if `crease`.
+    fn terrain(crease: bool) -> Chunk {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+        iter::iter_xzy(Chunk::SIZE, |lp| {
+            if lp.y < 8 || (crease && lp.x < 8) {
+                chunk.set_cell(lp, &Cell::new(mtl::STONE, VoxShape::Isosurface, 0.5));
+            }
+        });
+        chunk
+    }
+
+    #[test]
+    fn sn_ao_flat_and_crease() {
+        // the FeaturePoint of the cell is on the surface, between y=7 and y=8.
+        let lp = ivec3(8, 7, 8);
+        let flat = MeshGen::sn_ao(lp, &SampledField::new(&terrain(false), 1));
+        assert!((flat - 1.0).abs() < 1e-3, "flat {flat}");
+
+        let crease = MeshGen::sn_ao(lp, &SampledField::new(&terrain(true), 1));
+        assert!(crease < flat, "crease {crease}, flat {flat}");
+        assert!(crease >= AO_LEVELS[0]);
+    }
+}
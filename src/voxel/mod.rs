@@ -1,20 +1,43 @@
+mod biome;
+mod block_update;
+mod carver;
 mod chunk;
+mod chunk_storage;
+mod fluid;
+pub mod lighting;
 mod material;
-mod meshgen;
+pub mod meshgen;
+mod model;
+mod ore;
+mod palette;
+pub mod preset;
+mod structure;
 mod voxel_client;
 mod voxel_server;
 pub mod worldgen;
 
+pub use biome::{Biome, Climate};
+pub use block_update::{BlockBehavior, BlockBehaviors, BlockUpdates, BlockWorld};
+pub use carver::CaveConfig;
 pub use chunk::{Cell, Chunk, VoxShape, Vox};
-pub use voxel_client::{ClientChunkSystem, ClientVoxelPlugin, HitResult, VoxelBrush};
+pub use chunk_storage::{ChunkLoader, WorldMeta};
+pub use fluid::FluidSim;
+pub use material::mtl;
+pub use model::{mdl, VoxModel, VoxModels};
+pub use ore::OreConfig;
+pub use palette::PalettedCells;
+pub use voxel_client::{chunk_lod, ClientChunkSystem, ClientVoxelPlugin, HitResult, VoxelBrush};
 pub use voxel_server::{ServerChunkSystem, ServerVoxelPlugin};
+pub use preset::{WorldGenPreset, WorldGenPresets};
+pub use structure::StructureConfig;
 pub use worldgen::WorldGen;
 
-use crate::util::AsRefMut;
 use bevy::{prelude::*, utils::HashMap};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
-pub type ChunkPtr = Arc<Chunk>;
+// Chunks are shared with async meshing/saving tasks. the main thread takes the write lock to modify a chunk,
+// tasks take the read lock. never hold a chunk lock while locking another chunk for write, or it may deadlock.
+pub type ChunkPtr = Arc<RwLock<Chunk>>;
 
 #[derive(Resource, Deref, Clone)]
 struct ChannelTx<T>(crate::channel_impl::Sender<T>);
@@ -53,19 +76,13 @@ pub trait ChunkSystem {
     fn get_cell(&self, p: IVec3) -> Option<Cell> {
         let chunkptr = self.get_chunk(Chunk::as_chunkpos(p))?;
 
-        Some(*chunkptr.get_cell(Chunk::as_localpos(p)))
-    }
-
-    fn get_voxel(&self, p: IVec3) -> Option<&Cell> {
-        let chunkptr = self.get_chunk(Chunk::as_chunkpos(p))?;
-
-        Some(chunkptr.get_cell(Chunk::as_localpos(p)))
+        Some(*chunkptr.read().unwrap().get_cell(Chunk::as_localpos(p)))
     }
 
     fn set_voxel(&mut self, p: IVec3, v: &Cell) -> Option<()> {
         let chunkptr = self.get_chunk(Chunk::as_chunkpos(p))?;
 
-        chunkptr.as_ref_mut().set_cell(Chunk::as_localpos(p), v);
+        chunkptr.write().unwrap().set_cell(Chunk::as_localpos(p), v);
         Some(())
     }
 }
@@ -0,0 +1,85 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::VertexAttributeValues, render_resource::PrimitiveTopology},
+};
+
+use super::meshgen::Vertex;
+
+/// Models of `VoxShape::CustomMesh` cells, indexed by `mesh_id`.
+/// Models are in the cell space: x, z in [-0.5, 0.5] centered, y in [0, 1] from the bottom. the front faces +Z.
+pub mod mdl {
+    pub const FRAME: u16 = 0;
+    pub const LANTERN: u16 = 1;
+    pub const CHAIR: u16 = 2;
+
+    /// number of mesh_id.
+    pub const COUNT: u16 = 3;
+
+    /// Asset paths, loaded by the .obj or glTF loader. a glTF mesh is referenced by label, e.g. `a.glb#Mesh0/Primitive0`.
+    pub const PATHS: [&str; COUNT as usize] = ["models/frame.obj", "models/lantern.obj", "models/chair.obj"];
+
+    /// Block light level emitted by the model, see `lighting`. 0: not emissive.
+    pub const EMISSION: [u8; COUNT as usize] = [0, 15, 0];
+}
+
+/// Triangles of a model, baked into chunk meshes at meshing time. uv are the model's own [0, 1] uv, mapped to the cell's texture.
+pub struct VoxModel {
+    pub vertices: Vec<Vertex>,
+}
+
+impl VoxModel {
+    pub fn from_mesh(mesh: &Mesh) -> anyhow::Result<Self> {
+        if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+            anyhow::bail!("unsupported topology {:?}, expected TriangleList", mesh.primitive_topology());
+        }
+        let Some(positions) = mesh.attribute(Mesh::ATTRIBUTE_POSITION).and_then(|a| a.as_float3()) else {
+            anyhow::bail!("the mesh has no positions");
+        };
+        let normals = mesh.attribute(Mesh::ATTRIBUTE_NORMAL).and_then(|a| a.as_float3());
+        let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+            Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+            _ => None,
+        };
+
+        let indices: Vec<usize> = match mesh.indices() {
+            Some(indices) => indices.iter().collect(),
+            None => (0..positions.len()).collect(),
+        };
+        if let Some(i) = indices.iter().find(|i| **i >= positions.len()) {
+            anyhow::bail!("index {} out of {} vertices", i, positions.len());
+        }
+
+        let mut vertices: Vec<Vertex> = indices
+            .iter()
+            .map(|&i| Vertex {
+                pos: Vec3::from(positions[i]),
+                uv: uvs.map_or(Vec2::ZERO, |uvs| Vec2::from(uvs[i])),
+                norm: normals.map_or(Vec3::ZERO, |n| Vec3::from(n[i])),
+                ao: 1.0,
+                light: Vec2::ONE,
+            })
+            .collect();
+        vertices.truncate(vertices.len() / 3 * 3);
+
+        // flat normals if the model doesn't have.
+        if normals.is_none() {
+            for tri in vertices.chunks_exact_mut(3) {
+                let n = (tri[1].pos - tri[0].pos).cross(tri[2].pos - tri[0].pos).normalize_or_zero();
+                tri.iter_mut().for_each(|v| v.norm = n);
+            }
+        }
+        Ok(Self { vertices })
+    }
+}
+
+/// Loaded models. a model is None if it's not loaded (yet) or failed, cells of it are not meshed.
+#[derive(Default)]
+pub struct VoxModels {
+    pub models: Vec<Option<VoxModel>>,
+}
+
+impl VoxModels {
+    pub fn get(&self, mesh_id: u16) -> Option<&VoxModel> {
+        self.models.get(mesh_id as usize)?.as_ref()
+    }
+}
@@ -0,0 +1,75 @@
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{material::mtl, Chunk};
+use crate::util::hash_ivec3;
+
+/// Distribution of an ore. veins only replace stone.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OreConfig {
+    pub tex_id: u16,
+
+    // veins are only placed in this height range.
+    pub min_y: i32,
+    pub max_y: i32,
+
+    // number of cells of a vein, at most.
+    pub vein_size: u32,
+    // vein attempts per chunk. attempts out of the height range are dropped.
+    pub veins_per_chunk: u32,
+}
+
+impl OreConfig {
+    pub fn defaults() -> Vec<OreConfig> {
+        vec![
+            OreConfig {
+                tex_id: mtl::COAL_ORE,
+                min_y: -96,
+                max_y: 64,
+                vein_size: 14,
+                veins_per_chunk: 6,
+            },
+            OreConfig {
+                tex_id: mtl::IRON_ORE,
+                min_y: -160,
+                max_y: 16,
+                vein_size: 8,
+                veins_per_chunk: 4,
+            },
+        ]
+    }
+}
+
+/// Place ore veins in the chunk, seeded by the world seed and chunkpos.
+/// Veins are clipped at the chunk border, so a chunk doesn't depend on its neighbors.
+pub fn place_ores(seed: u64, ores: &[OreConfig], chunk: &mut Chunk) {
+    let chunkpos = chunk.chunkpos;
+
+    for (i, ore) in ores.iter().enumerate() {
+        if chunkpos.y + Chunk::SIZE <= ore.min_y || chunkpos.y >= ore.max_y {
+            continue;
+        }
+        let mut rng = ChaCha8Rng::seed_from_u64(seed ^ hash_ivec3(chunkpos) ^ (i as u64 + 1).wrapping_mul(0x2545F4914F6CDD1D));
+
+        for _ in 0..ore.veins_per_chunk {
+            let mut lp = IVec3::new(rng.gen_range(0..Chunk::SIZE), rng.gen_range(0..Chunk::SIZE), rng.gen_range(0..Chunk::SIZE));
+            let y = chunkpos.y + lp.y;
+            if y < ore.min_y || y >= ore.max_y {
+                continue;
+            }
+
+            // random walk of axis steps makes a clumpy vein.
+            for _ in 0..rng.gen_range(ore.vein_size / 2..=ore.vein_size) {
+                if Chunk::is_localpos(lp) && chunk.get_cell(lp).tex_id == mtl::STONE {
+                    let mut c = *chunk.get_cell(lp);
+                    c.tex_id = ore.tex_id;
+                    chunk.set_cell(lp, &c);
+                }
+                let axis = rng.gen_range(0..3);
+                lp[axis] += if rng.gen() { 1 } else { -1 };
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::ops::{Deref, DerefMut};
+
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+
+use super::Cell;
+
+// Palette-Compressed Cells of a Chunk.
+//   palette: the distinct cells in the chunk.
+//   data:    bit-packed palette indices of the 4096 cells in local_idx order. an index never spans 2 words.
+//
+// Most chunks have only a few kinds of cells (air, stone, water, a few surface isovalues),
+// so 4096 cells usually takes 1~8 bits each instead of 4 bytes. A chunk with only one kind of cell
+// (e.g. all air underground/sky chunks) stores just the palette, with bits = 0 and no data.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PalettedCells {
+    palette: Vec<Cell>,
+    bits: u32,
+    data: Vec<u64>,
+
+    // palette index of cells, to find a cell in a big palette (surface chunks have hundreds of isovalues).
+    // not serialized, built on the first set().
+    #[serde(skip)]
+    lookup: HashMap<Cell, usize>,
+}
+
+impl PalettedCells {
+    const NUM_CELLS: usize = 16 * 16 * 16;
+
+    pub fn new(cell: Cell) -> Self {
+        Self {
+            palette: vec![cell],
+            bits: 0,
+            data: Vec::new(),
+            lookup: HashMap::default(),
+        }
+    }
+
+    pub fn palette(&self) -> &[Cell] {
+        &self.palette
+    }
+
+    /// Only one kind of cell in the chunk. no index data stored.
+    pub fn is_uniform(&self) -> bool {
+        self.bits == 0
+    }
+
+    // minimal bits to index a palette of `n` entries.
+    fn bits_for(n: usize) -> u32 {
+        if n <= 1 {
+            0
+        } else {
+            usize::BITS - (n - 1).leading_zeros()
+        }
+    }
+
+    fn num_words(bits: u32) -> usize {
+        if bits == 0 {
+            0
+        } else {
+            Self::NUM_CELLS.div_ceil((u64::BITS / bits) as usize)
+        }
+    }
+
+    fn get_index(&self, idx: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = (u64::BITS / self.bits) as usize;
+        let shift = (idx % per_word) as u32 * self.bits;
+        ((self.data[idx / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_index(&mut self, idx: usize, val: usize) {
+        let per_word = (u64::BITS / self.bits) as usize;
+        let shift = (idx % per_word) as u32 * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.data[idx / per_word];
+        *word = (*word & !mask) | ((val as u64) << shift);
+    }
+
+    // repack all indices with new bits width. `remap` maps old palette index to the new one.
+    fn repack(&mut self, bits: u32, remap: impl Fn(usize) -> usize) {
+        let indices: Vec<usize> = (0..Self::NUM_CELLS).map(|i| remap(self.get_index(i))).collect();
+
+        self.bits = bits;
+        self.data = vec![0; Self::num_words(bits)];
+        if bits != 0 {
+            for (i, val) in indices.into_iter().enumerate() {
+                self.set_index(i, val);
+            }
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> &Cell {
+        &self.palette[self.get_index(idx)]
+    }
+
+    pub fn set(&mut self, idx: usize, cell: &Cell) {
+        if self.lookup.is_empty() {
+            self.lookup = self.palette.iter().enumerate().map(|(i, c)| (*c, i)).collect();
+        }
+        let val = match self.lookup.get(cell) {
+            Some(val) => *val,
+            None => {
+                if self.palette.len() >= 1 << self.bits {
+                    // palette is full. drop unused entries first, grow bits if it's still mostly full,
+                    // otherwise every few new cells would compact again, e.g. editing isovalues of a surface chunk.
+                    self.compact();
+                    if self.palette.len() * 4 > (1 << self.bits) * 3 {
+                        self.repack(self.bits + 1, |i| i);
+                    }
+                }
+                self.palette.push(*cell);
+                self.lookup.insert(*cell, self.palette.len() - 1);
+                self.palette.len() - 1
+            }
+        };
+        if self.bits != 0 {
+            self.set_index(idx, val);
+        }
+    }
+
+    /// Remove unused palette entries, and shrink bits width to fit.
+    pub fn compact(&mut self) {
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..Self::NUM_CELLS {
+            used[self.get_index(i)] = true;
+        }
+        if used.iter().all(|u| *u) {
+            return;
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (i, cell) in self.palette.iter().enumerate() {
+            if used[i] {
+                remap[i] = palette.len();
+                palette.push(*cell);
+            }
+        }
+
+        self.repack(Self::bits_for(palette.len()), |i| remap[i]);
+        self.lookup = palette.iter().enumerate().map(|(i, c)| (*c, i)).collect();
+        self.palette = palette;
+    }
+
+    /// Check the consistency of deserialized data, before it's indexed.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.palette.is_empty() || self.bits > 16 || self.palette.len() > 1 << self.bits {
+            anyhow::bail!("invalid palette, {} entries, {} bits", self.palette.len(), self.bits);
+        }
+        if self.data.len() != Self::num_words(self.bits) {
+            anyhow::bail!("invalid palette data length {}", self.data.len());
+        }
+        if (0..Self::NUM_CELLS).any(|i| self.get_index(i) >= self.palette.len()) {
+            anyhow::bail!("palette index out of range");
+        }
+        Ok(())
+    }
+}
+
+impl Default for PalettedCells {
+    fn default() -> Self {
+        Self::new(Cell::default())
+    }
+}
+
+/// Mutable access to a cell in PalettedCells. the modified cell is written back on drop.
+pub struct CellMut<'a> {
+    cells: &'a mut PalettedCells,
+    idx: usize,
+    cell: Cell,
+}
+
+impl<'a> CellMut<'a> {
+    pub fn new(cells: &'a mut PalettedCells, idx: usize) -> Self {
+        let cell = *cells.get(idx);
+        Self { cells, idx, cell }
+    }
+}
+
+impl Deref for CellMut<'_> {
+    type Target = Cell;
+    fn deref(&self) -> &Cell {
+        &self.cell
+    }
+}
+
+impl DerefMut for CellMut<'_> {
+    fn deref_mut(&mut self) -> &mut Cell {
+        &mut self.cell
+    }
+}
+
+impl Drop for CellMut<'_> {
+    fn drop(&mut self) {
+        self.cells.set(self.idx, &self.cell);
+    }
+}
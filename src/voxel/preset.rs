@@ -0,0 +1,202 @@
+use bevy::{asset::LoadState, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use super::{biome::Biome, carver::CaveConfig, ore::OreConfig, structure::StructureConfig};
+
+/// WorldGen Preset. All parameters of the world generator, loaded from `assets/worldgen/{name}.worldgen.json`.
+/// A new world copies the selected preset into its world meta, so editing preset files won't change existing worlds.
+#[derive(Asset, TypePath, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldGenPreset {
+    pub terrain: TerrainConfig,
+    pub surface: SurfaceConfig,
+    pub features: FeatureConfig,
+    pub caves: CaveConfig,
+    pub ores: Vec<OreConfig>,
+    pub structures: StructureConfig,
+}
+
+impl Default for WorldGenPreset {
+    fn default() -> Self {
+        Self {
+            terrain: TerrainConfig::default(),
+            surface: SurfaceConfig::default(),
+            features: FeatureConfig::default(),
+            caves: CaveConfig::default(),
+            ores: OreConfig::defaults(),
+            structures: StructureConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TerrainKind {
+    /// Heightfield and 3D noise, shaped by biomes.
+    Noise,
+    /// Flat ground at `flat_height`.
+    Flat,
+    /// Nothing but a spawn platform at the origin.
+    Void,
+}
+
+/// A Fbm noise layer. sampled at `pos / scale`, multiplied by `amplitude`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct NoiseLayer {
+    pub octaves: usize,
+    pub scale: f64,
+    pub amplitude: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainConfig {
+    pub kind: TerrainKind,
+
+    // 2D heightfield, further scaled by biomes.
+    pub height_noise: NoiseLayer,
+    // 3D noise, makes overhangs and floating islands.
+    pub detail_noise: NoiseLayer,
+    // blocks per unit of terrain isovalue. greater makes the noise shift the surface more.
+    pub height_falloff: f32,
+    // added to the water level of biomes.
+    pub sea_level: f32,
+
+    // Flat terrain. surface at y = flat_height - 1.
+    pub flat_height: i32,
+
+    // generate the whole world as this biome, instead of the climate.
+    pub biome: Option<Biome>,
+
+    // Climate noise scales, in blocks. greater makes bigger biomes.
+    pub temperature_scale: f64,
+    pub humidity_scale: f64,
+    pub continentalness_scale: f64,
+    // positive makes more land than ocean.
+    pub continentalness_bias: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            kind: TerrainKind::Noise,
+            height_noise: NoiseLayer {
+                octaves: 5,
+                scale: 130.0,
+                amplitude: 1.0,
+            },
+            detail_noise: NoiseLayer {
+                octaves: 5,
+                scale: 90.0,
+                amplitude: 4.5,
+            },
+            height_falloff: 18.0,
+            sea_level: 0.0,
+            flat_height: 0,
+            biome: None,
+            temperature_scale: 600.0,
+            humidity_scale: 500.0,
+            continentalness_scale: 900.0,
+            continentalness_bias: 0.25,
+        }
+    }
+}
+
+/// Surface rules. the top stone of a column is replaced by the biome surface/subsurface materials.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SurfaceConfig {
+    // depth of the biome surface material, and of the subsurface material below it.
+    pub surface_depth: u32,
+    pub subsurface_depth: u32,
+
+    // sand shores, up to this height above the water level where the shore noise > threshold.
+    pub shore_height: f32,
+    pub shore_threshold: f64,
+}
+
+impl Default for SurfaceConfig {
+    fn default() -> Self {
+        Self {
+            surface_depth: 1,
+            subsurface_depth: 1,
+            shore_height: 2.0,
+            shore_threshold: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeatureConfig {
+    pub grass: bool,
+    pub grass_scale: f64,
+    // grass noise thresholds of the biome foliage bands, see `BiomeDef::foliage`.
+    pub foliage_bands: [f32; 3],
+
+    pub vines: bool,
+    // chance of vines per column.
+    pub vine_chance: f32,
+    pub vine_length: f32,
+
+    pub trees: bool,
+    // multiplies the tree chance of biomes.
+    pub tree_density: f32,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        Self {
+            grass: true,
+            grass_scale: 18.0,
+            foliage_bands: [0.94, 0.8, 0.24],
+            vines: true,
+            vine_chance: 18.0 / 256.0,
+            vine_length: 12.0,
+            trees: true,
+            tree_density: 1.0,
+        }
+    }
+}
+
+/// Handles of the presets in `assets/worldgen`, by name.
+#[derive(Resource, Default)]
+pub struct WorldGenPresets {
+    pub handles: HashMap<String, Handle<WorldGenPreset>>,
+}
+
+impl WorldGenPresets {
+    pub const BUILTIN: [&'static str; 4] = ["default", "superflat", "void", "amplified"];
+
+    pub fn load(asset_server: &AssetServer) -> Self {
+        let handles = Self::BUILTIN
+            .iter()
+            .map(|name| (name.to_string(), asset_server.load(format!("worldgen/{name}.worldgen.json"))))
+            .collect();
+        Self { handles }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.handles.keys().map(|s| s.as_str()).collect();
+        names.sort_by_key(|name| Self::BUILTIN.iter().position(|b| b == name).unwrap_or(usize::MAX));
+        names
+    }
+
+    /// The preset of the name. None if it's still loading.
+    /// Unknown or failed presets fall back to the built-in default parameters.
+    pub fn get(&self, name: &str, assets: &Assets<WorldGenPreset>, asset_server: &AssetServer) -> Option<WorldGenPreset> {
+        let Some(handle) = self.handles.get(name) else {
+            error!("Unknown worldgen preset '{}', using the default", name);
+            return Some(WorldGenPreset::default());
+        };
+        if let Some(preset) = assets.get(handle) {
+            return Some(preset.clone());
+        }
+        match asset_server.load_state(handle) {
+            LoadState::Failed => {
+                error!("Failed to load worldgen preset '{}', using the default", name);
+                Some(WorldGenPreset::default())
+            }
+            _ => None,
+        }
+    }
+}
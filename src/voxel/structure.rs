@@ -0,0 +1,276 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{prelude::*, utils::HashMap};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{material::mtl, Cell, Chunk, VoxShape};
+use crate::{
+    util::{hash, hash_ivec3},
+    wfc::WFC,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StructureConfig {
+    pub enabled: bool,
+    // the world is divided into square regions of this size in blocks. each region has a structure by the chance.
+    pub region_size: i32,
+    pub chance: f32,
+    // size of the WFC grid in tiles (x, z). should fit in a region.
+    pub extent: [i32; 2],
+}
+
+impl Default for StructureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            region_size: 256,
+            chance: 0.4,
+            extent: [7, 7],
+        }
+    }
+}
+
+/// Tile template of a structure tileset. `cells` are indexed `(y * SIZE + z) * SIZE + x`, None keeps the terrain.
+pub struct StructureTile {
+    pub name: &'static str,
+    // sockets of the WFC pattern. [-X, +X, -Y, +Y, -Z, +Z]
+    pub sockets: [u32; 6],
+    pub rotate: bool,
+    pub flip: bool,
+    pub cells: Vec<Option<Cell>>,
+}
+
+impl StructureTile {
+    pub const SIZE: i32 = 5;
+    pub const HEIGHT: i32 = 6;
+
+    /// Build from ascii layers, bottom to top. each layer is SIZE rows (z) of SIZE chars (x).
+    /// ' ' keeps the terrain, '.' air, '#' stone, '_' floor.
+    fn from_layers(name: &'static str, sockets: [u32; 6], rotate: bool, flip: bool, layers: &[[&str; 5]; 6]) -> Self {
+        let cells = layers
+            .iter()
+            .flat_map(|layer| layer.iter().flat_map(|row| row.chars()))
+            .map(|ch| match ch {
+                '.' => Some(Cell::new(mtl::NIL, VoxShape::Isosurface, -0.5)),
+                '#' => Some(Cell::new(mtl::STONE, VoxShape::Cube, 0.5)),
+                '_' => Some(Cell::new(mtl::GRAVEL, VoxShape::Cube, 0.5)),
+                _ => None,
+            })
+            .collect();
+        Self {
+            name,
+            sockets,
+            rotate,
+            flip,
+            cells,
+        }
+    }
+
+    /// Cell of the tile placed with the pattern `rotation` and `flipped`, consistent with the WFC socket rotation/flip.
+    fn get(&self, p: IVec3, rotation: u8, flipped: bool) -> Option<Cell> {
+        let n = Self::SIZE - 1;
+        let (mut x, mut z) = (p.x, p.z);
+        for _ in 0..rotation {
+            (x, z) = (n - z, x);
+        }
+        if flipped {
+            x = n - x;
+        }
+        self.cells[((p.y * Self::SIZE + z) * Self::SIZE + x) as usize]
+    }
+}
+
+/// Tileset of ruins: walled rooms of various shapes, surrounded by untouched ground.
+/// sockets: 0 ground, 1 room floor, 2 wall through the tile center.
+fn ruins_tileset() -> Vec<StructureTile> {
+    const FLOOR: [&str; 5] = ["_____"; 5];
+    const AIR: [&str; 5] = ["....."; 5];
+    const WALL: [&str; 5] = ["..#.."; 5];
+    vec![
+        StructureTile::from_layers("ground", [0; 6], false, false, &[["     "; 5]; 6]),
+        StructureTile::from_layers("floor", [1, 1, 0, 0, 1, 1], false, false, &[FLOOR, AIR, AIR, AIR, AIR, AIR]),
+        StructureTile::from_layers("wall", [0, 1, 0, 0, 2, 2], true, false, &[FLOOR, WALL, WALL, WALL, WALL, AIR]),
+        StructureTile::from_layers(
+            "door",
+            [0, 1, 0, 0, 2, 2],
+            true,
+            false,
+            &[FLOOR, ["..#..", "..#..", ".....", "..#..", "..#.."], ["..#..", "..#..", ".....", "..#..", "..#.."], WALL, WALL, AIR],
+        ),
+        StructureTile::from_layers(
+            "corner",
+            [0, 2, 0, 0, 0, 2],
+            true,
+            false,
+            &[FLOOR, [".....", ".....", "..###", "..#..", "..#.."], [".....", ".....", "..###", "..#..", "..#.."], [".....", ".....", "..###", "..#..", "..#.."], [".....", ".....", "..###", "..#..", "..#.."], AIR],
+        ),
+    ]
+}
+
+/// A generated structure. a box of cells in world space, None keeps the terrain.
+pub struct Structure {
+    pub min: IVec3,
+    pub size: IVec3,
+    cells: Vec<Option<Cell>>,
+}
+
+impl Structure {
+    fn idx(&self, lp: IVec3) -> usize {
+        ((lp.y * self.size.z + lp.z) * self.size.x + lp.x) as usize
+    }
+
+    /// Write the part of the structure inside the chunk.
+    pub fn write_chunk(&self, chunk: &mut Chunk) {
+        let min = (self.min - chunk.chunkpos).max(IVec3::ZERO);
+        let max = (self.min + self.size - chunk.chunkpos).min(IVec3::splat(Chunk::SIZE));
+        for ly in min.y..max.y {
+            for lz in min.z..max.z {
+                for lx in min.x..max.x {
+                    let lp = IVec3::new(lx, ly, lz);
+                    if let Some(c) = self.cells[self.idx(chunk.chunkpos + lp - self.min)] {
+                        chunk.set_cell(lp, &c);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Places WFC generated structures. At most one structure per region, anchored and solved with a rng seeded by
+/// the world seed and the region, so every chunk (on any client/server) reproduces the same structure and writes its own part.
+pub struct StructureGen {
+    pub config: StructureConfig,
+    seed: u64,
+    tiles: Vec<StructureTile>,
+
+    // solved structures of regions. only a cache, regenerating gives the same result.
+    cache: Mutex<HashMap<IVec2, Option<Arc<Structure>>>>,
+}
+
+impl StructureGen {
+    // foundation layers under the floor, avoids floating floors on slopes.
+    const FOUNDATION: i32 = 3;
+
+    pub fn new(seed: u64, config: StructureConfig) -> Self {
+        Self {
+            config,
+            seed,
+            tiles: ruins_tileset(),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Write structures overlapping the chunk. `surface_y` gives the ground height of a column, None if a structure can't be placed there.
+    pub fn write_chunk(&self, chunk: &mut Chunk, surface_y: impl Fn(i32, i32) -> Option<i32>) {
+        if !self.config.enabled || self.config.region_size <= 0 {
+            return;
+        }
+        let rs = self.config.region_size;
+        let min = chunk.chunkpos.xz().div_euclid(IVec2::splat(rs));
+        let max = (chunk.chunkpos.xz() + Chunk::SIZE - 1).div_euclid(IVec2::splat(rs));
+
+        for rx in min.x..=max.x {
+            for rz in min.y..=max.y {
+                if let Some(structure) = self.structure_at(IVec2::new(rx, rz), &surface_y) {
+                    structure.write_chunk(chunk);
+                }
+            }
+        }
+    }
+
+    fn structure_at(&self, region: IVec2, surface_y: &impl Fn(i32, i32) -> Option<i32>) -> Option<Arc<Structure>> {
+        if let Some(cached) = self.cache.lock().unwrap().get(&region) {
+            return cached.clone();
+        }
+        // don't hold the cache while generating.
+        let structure = self.generate(region, surface_y).map(Arc::new);
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() > 1024 {
+            cache.clear();
+        }
+        cache.insert(region, structure.clone());
+        structure
+    }
+
+    fn generate(&self, region: IVec2, surface_y: &impl Fn(i32, i32) -> Option<i32>) -> Option<Structure> {
+        let cfg = &self.config;
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed ^ hash_ivec3(IVec3::new(region.x, 0, region.y)) ^ 0x5354_5255_4354);
+        if rng.gen::<f32>() >= cfg.chance {
+            return None;
+        }
+
+        let extent = IVec2::from(cfg.extent).max(IVec2::ONE);
+        let size_xz = extent * StructureTile::SIZE;
+        let margin = IVec2::splat(cfg.region_size) - size_xz;
+        if margin.min_element() < 0 {
+            return None;
+        }
+        let min_xz = region * cfg.region_size + IVec2::new(rng.gen_range(0..=margin.x), rng.gen_range(0..=margin.y));
+        let center = min_xz + size_xz / 2;
+        let ground_y = surface_y(center.x, center.y)?;
+
+        // Solve the layout. a room is forced at the center, boundary tiles are ground so rooms aren't cut off.
+        let mut wfc = WFC::new();
+        for tile in self.tiles.iter() {
+            wfc.push_pattern(tile.name.into(), tile.sockets, tile.rotate, tile.flip);
+        }
+        wfc.init_tiles(IVec3::new(extent.x, 1, extent.y));
+        wfc.constrain_boundary(0);
+        wfc.constrain_tile(IVec3::new(extent.x / 2, 0, extent.y / 2), |pat| pat.name == "floor");
+        if let Err(err) = wfc.run_with_rng(&mut rng) {
+            warn!("Skipped structure of region {}. {}", region, err);
+            return None;
+        }
+
+        let mut structure = Structure {
+            min: IVec3::new(min_xz.x, ground_y - Self::FOUNDATION, min_xz.y),
+            size: IVec3::new(size_xz.x, StructureTile::HEIGHT + Self::FOUNDATION, size_xz.y),
+            cells: Vec::new(),
+        };
+        structure.cells = vec![None; (structure.size.x * structure.size.y * structure.size.z) as usize];
+
+        let fs = (self.seed ^ (self.seed >> 32)) as i32;
+        for wfc_tile in wfc.tiles.iter() {
+            let pat = &wfc.all_patterns[wfc_tile.possib[0] as usize];
+            let Some(tile) = self.tiles.iter().find(|t| t.name == pat.name) else {
+                continue;
+            };
+            let tile_min = IVec3::new(wfc_tile.pos.x * StructureTile::SIZE, Self::FOUNDATION, wfc_tile.pos.z * StructureTile::SIZE);
+
+            for y in 0..StructureTile::HEIGHT {
+                for z in 0..StructureTile::SIZE {
+                    for x in 0..StructureTile::SIZE {
+                        let lp = IVec3::new(x, y, z);
+                        let Some(mut c) = tile.get(lp, pat.rotation, pat.is_flipped) else {
+                            continue;
+                        };
+                        let sp = tile_min + lp;
+                        let p = structure.min + sp;
+                        let h = hash(p.x ^ (p.y * 3719) ^ (p.z * 9377) ^ fs);
+
+                        // Ruined. walls crumble more towards the top, floors are mossy.
+                        if c.tex_id == mtl::STONE && h < (y - 1) as f32 * 0.15 {
+                            c = Cell::new(mtl::NIL, VoxShape::Isosurface, -0.5);
+                        } else if c.tex_id == mtl::GRAVEL && h < 0.2 {
+                            c.tex_id = mtl::MOSS;
+                        }
+                        let idx = structure.idx(sp);
+                        structure.cells[idx] = Some(c);
+
+                        if y == 0 && !c.is_tex_empty() {
+                            for fy in 0..Self::FOUNDATION {
+                                let idx = structure.idx(IVec3::new(sp.x, fy, sp.z));
+                                structure.cells[idx] = Some(Cell::new(mtl::STONE, VoxShape::Isosurface, 0.5));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Some(structure)
+    }
+}
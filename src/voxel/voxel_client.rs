@@ -1,5 +1,6 @@
 use bevy::{
     asset::ReflectAsset,
+    pbr::NotShadowCaster,
     prelude::*,
     render::{
         render_asset::RenderAssetUsages,
@@ -12,9 +13,10 @@ use bevy_xpbd_3d::plugins::{
     collision::Collider,
     spatial_query::{SpatialQuery, SpatialQueryFilter},
 };
+use bevy_renet::renet::RenetClient;
 use leafwing_input_manager::action_state::ActionState;
 
-use super::{meshgen::MeshGen, ChannelRx, ChannelTx, Chunk, ChunkPtr, ChunkSystem, VoxShape};
+use super::{lighting, mdl, meshgen::MeshGen, ChannelRx, ChannelTx, Chunk, ChunkPtr, ChunkSystem, VoxModel, VoxModels, VoxShape, WorldGen};
 use crate::{
     client::{
         character_controller::{CharacterController, CharacterControllerCamera},
@@ -22,7 +24,8 @@ use crate::{
         prelude::{ClientSettings, InputAction},
         ui::CurrentUI,
     },
-    util::{iter, AsRefMut},
+    net::{CPacket, CellData, RenetClientHelper},
+    util::iter,
 };
 
 pub struct ClientVoxelPlugin;
@@ -52,9 +55,21 @@ impl Plugin for ClientVoxelPlugin {
         app.insert_resource(HitResult::default());
         app.register_type::<HitResult>();
 
+        // Models of CustomMesh cells.
+        app.insert_resource(VoxModelAssets::default());
+        app.add_systems(Startup, load_vox_models);
+        app.add_systems(Update, build_vox_models);
+
         app.add_systems(
             Update,
-            (chunks_detect_load_and_unload, chunks_remesh_enqueue, raycast, draw_gizmos, draw_crosshair_cube)
+            (
+                chunks_detect_load_and_unload,
+                chunks_update_lod,
+                chunks_remesh_enqueue,
+                raycast,
+                draw_gizmos,
+                draw_crosshair_cube,
+            )
                 .chain()
                 .run_if(condition::in_world),
         );
@@ -86,6 +101,17 @@ fn on_world_init(
         ..default()
     });
 
+    // double sided, the surface is seen from underwater too.
+    chunk_sys.mtl_fluid = std_mtls.add(StandardMaterial {
+        base_color: Color::rgba(0.1, 0.35, 0.55, 0.7),
+        perceptual_roughness: 0.1,
+        reflectance: 0.6,
+        double_sided: true,
+        alpha_mode: AlphaMode::Blend,
+        cull_mode: None,
+        ..default()
+    });
+
     // ChunkSystem entity. all chunk entities will be spawn as children. (for almost no reason. just for editor hierarchy)
     chunk_sys.entity = cmds
         .spawn((
@@ -105,6 +131,57 @@ fn on_world_exit(mut cmds: Commands) {
     cmds.remove_resource::<ClientChunkSystem>();
 }
 
+#[derive(Resource, Default)]
+pub struct VoxModelAssets {
+    handles: Vec<Handle<Mesh>>,
+    built: bool,
+
+    // shared with meshing tasks. empty until all models are loaded.
+    pub models: Arc<VoxModels>,
+}
+
+fn load_vox_models(mut vox_models: ResMut<VoxModelAssets>, asset_server: Res<AssetServer>) {
+    vox_models.handles = mdl::PATHS.iter().map(|path| asset_server.load(*path)).collect();
+}
+
+// Build models once all of them are loaded (or failed). chunks meshed before are remeshed with the models.
+fn build_vox_models(
+    mut vox_models: ResMut<VoxModelAssets>,
+    asset_server: Res<AssetServer>,
+    meshes: Res<Assets<Mesh>>,
+    chunk_sys: Option<ResMut<ClientChunkSystem>>,
+) {
+    use bevy::asset::LoadState;
+    if vox_models.built
+        || !vox_models
+            .handles
+            .iter()
+            .all(|h| matches!(asset_server.get_load_state(h), Some(LoadState::Loaded | LoadState::Failed)))
+    {
+        return;
+    }
+
+    let models = vox_models
+        .handles
+        .iter()
+        .zip(mdl::PATHS)
+        .map(|(h, path)| {
+            let model = meshes.get(h).map_or_else(|| Err(anyhow::anyhow!("failed to load")), VoxModel::from_mesh);
+            model.map_err(|err| warn!("Failed to load model {}: {}", path, err)).ok()
+        })
+        .collect();
+    info!("Loaded {} voxel models", mdl::COUNT);
+    vox_models.models = Arc::new(VoxModels { models });
+    vox_models.built = true;
+
+    if let Some(mut chunk_sys) = chunk_sys {
+        let chunks: Vec<IVec3> = chunk_sys.chunks.keys().cloned().collect();
+        for chunkpos in chunks {
+            chunk_sys.mark_chunk_remesh(chunkpos);
+        }
+    }
+}
+
 type ChunkLoadingData = Chunk;
 
 fn chunks_detect_load_and_unload(
@@ -122,48 +199,121 @@ fn chunks_detect_load_and_unload(
     let vp = Chunk::as_chunkpos(query_cam.single().translation.as_ivec3()); // viewer pos
     let vd = cfg.chunks_load_distance;
 
-    // Chunks Detect Load/Gen
-
-    iter::iter_center_spread(vd.x, vd.y, |rp| {
-        if chunks_loading.len() > 8 {
-            //chunk_sys.max_concurrent_loading {
-            return;
-        }
-        let chunkpos = rp * Chunk::SIZE + vp;
+    // Chunks Detect Load/Gen. (after the world seed is received)
+    if let Some(worldgen) = chunk_sys.worldgen.clone() {
+        iter::iter_center_spread(vd.x, vd.y, |rp| {
+            if chunks_loading.len() > 8 {
+                //chunk_sys.max_concurrent_loading {
+                return;
+            }
+            let chunkpos = rp * Chunk::SIZE + vp;
 
-        // the chunk already exists, skip.
-        if chunk_sys.has_chunk(chunkpos) || chunks_loading.contains(&chunkpos) {
-            return;
-        }
+            // the chunk already exists, skip.
+            if chunk_sys.has_chunk(chunkpos) || chunks_loading.contains(&chunkpos) {
+                return;
+            }
 
-        let tx = tx_chunk_load.clone();
-        let task = AsyncComputeTaskPool::get().spawn(async move {
-            // info!("Load Chunk: {:?}", chunkpos);
-            let mut chunk = Chunk::new(chunkpos);
+            let tx = tx_chunk_load.clone();
+            let worldgen = worldgen.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                // info!("Load Chunk: {:?}", chunkpos);
+                let mut chunk = Chunk::new(chunkpos);
 
-            super::WorldGen::generate_chunk(&mut chunk);
+                worldgen.generate_chunk(&mut chunk);
+                // features placed by neighbor chunks generated earlier.
+                worldgen.apply_pending_writes(&mut chunk);
 
-            tx.send(chunk).unwrap();
+                tx.send(chunk).unwrap();
+            });
+            task.detach();
+            chunks_loading.insert(chunkpos);
         });
-        task.detach();
-        chunks_loading.insert(chunkpos);
-    });
+    }
 
+    let mut any_loaded = false;
     while let Ok(chunk) = rx_chunk_load.try_recv() {
         chunks_loading.remove(&chunk.chunkpos);
+        any_loaded = true;
 
         chunk_sys.spawn_chunk(chunk, &mut cmds, &mut meshes);
     }
+
+    // Features placed into already loaded chunks by the newly generated chunks.
+    if any_loaded {
+        if let Some(worldgen) = chunk_sys.worldgen.clone() {
+            for (chunkpos, cells) in worldgen.apply_pending_writes_loaded(&chunk_sys.chunks) {
+                chunk_sys.mark_chunk_remesh(chunkpos);
+
+                let positions: Vec<IVec3> = cells
+                    .iter()
+                    .map(|(local_idx, _)| chunkpos + Chunk::local_idx_pos(*local_idx as i32))
+                    .collect();
+                for cp in lighting::relight_cells(&*chunk_sys, &positions) {
+                    chunk_sys.mark_chunk_remesh(cp);
+                }
+            }
+        }
+    }
+}
+
+/// LOD of a chunk by the distance in chunks from the viewer. full resolution within `lod_distance`,
+/// then the next level every time the distance doubles.
+pub fn chunk_lod(chunkpos: IVec3, viewer_chunkpos: IVec3, lod_distance: i32) -> u8 {
+    let d = ((chunkpos - viewer_chunkpos) / Chunk::SIZE).abs().max_element();
+    let mut lod = 0;
+    let mut r = lod_distance.max(0);
+    while d > r && lod < MeshGen::MAX_LOD {
+        lod += 1;
+        r = r * 2 + 1;
+    }
+    lod
+}
+
+// Remesh chunks whose LOD changed, when the viewer moves to another chunk.
+fn chunks_update_lod(
+    query_cam: Query<&Transform, With<CharacterControllerCamera>>,
+    mut chunk_sys: ResMut<ClientChunkSystem>,
+    cfg: Res<ClientSettings>,
+    mut last_viewer: Local<Option<(IVec3, i32)>>,
+) {
+    let vp = Chunk::as_chunkpos(query_cam.single().translation.as_ivec3());
+    if *last_viewer == Some((vp, cfg.chunks_lod_distance)) {
+        return;
+    }
+    *last_viewer = Some((vp, cfg.chunks_lod_distance));
+
+    let changed: Vec<IVec3> = chunk_sys
+        .chunks_lod
+        .iter()
+        .filter(|(cp, lod)| chunk_lod(**cp, vp, cfg.chunks_lod_distance) != **lod)
+        .map(|(cp, _)| *cp)
+        .collect();
+    for chunkpos in changed {
+        chunk_sys.mark_chunk_remesh(chunkpos);
+    }
 }
 
-type ChunkRemeshData = (IVec3, Entity, Mesh, Handle<Mesh>, Option<Collider>, Mesh, Handle<Mesh>);
+type ChunkRemeshData = (
+    IVec3,
+    Entity,
+    Mesh,
+    Handle<Mesh>,
+    Option<Collider>,
+    Mesh,
+    Handle<Mesh>,
+    Mesh,
+    Handle<Mesh>,
+);
 
 use crate::voxel::meshgen::VertexBuffer;
 use once_cell::sync::Lazy;
-use std::{cell::RefCell, sync::Arc};
+use std::{
+    cell::RefCell,
+    sync::{Arc, RwLock},
+};
 use thread_local::ThreadLocal;
 
-static THREAD_LOCAL_VERTEX_BUFFERS: Lazy<ThreadLocal<RefCell<(VertexBuffer, VertexBuffer)>>> = Lazy::new(ThreadLocal::default);
+static THREAD_LOCAL_VERTEX_BUFFERS: Lazy<ThreadLocal<RefCell<(VertexBuffer, VertexBuffer, VertexBuffer)>>> = Lazy::new(ThreadLocal::default);
 
 fn chunks_remesh_enqueue(
     mut commands: Commands,
@@ -171,6 +321,8 @@ fn chunks_remesh_enqueue(
     query_cam: Query<&Transform, With<CharacterControllerCamera>>,
     mut chunk_sys: ResMut<ClientChunkSystem>,
     mut meshes: ResMut<Assets<Mesh>>,
+    vox_models: Res<VoxModelAssets>,
+    cfg: Res<ClientSettings>,
 
     tx_chunks_meshing: Res<ChannelTx<ChunkRemeshData>>,
     rx_chunks_meshing: Res<ChannelRx<ChunkRemeshData>>,
@@ -195,42 +347,50 @@ fn chunks_remesh_enqueue(
 
             let chunkptr = chunkptr.clone();
             let tx = tx_chunks_meshing.clone();
+            let models = vox_models.models.clone();
+            let lod = chunk_lod(chunkpos, cam_cp, cfg.chunks_lod_distance);
+            chunk_sys.chunks_lod.insert(chunkpos, lod);
 
             let task = AsyncComputeTaskPool::get().spawn(async move {
-                let mut _vbuf = THREAD_LOCAL_VERTEX_BUFFERS
-                    .get_or(|| RefCell::new((VertexBuffer::default(), VertexBuffer::default())))
-                    .borrow_mut();
-                // 0: vbuf_terrain, 1: vbuf_foliage
+                let mut _vbuf = THREAD_LOCAL_VERTEX_BUFFERS.get_or(|| RefCell::new(Default::default())).borrow_mut();
+                // 0: vbuf_terrain, 1: vbuf_foliage, 2: vbuf_fluid
 
                 // let dbg_time = Instant::now();
                 let entity;
                 let mesh_handle;
                 let mesh_handle_foliage;
+                let mesh_handle_fluid;
                 {
-                    let chunk = chunkptr.as_ref();
+                    let chunk = chunkptr.read().unwrap();
 
                     // Generate Mesh
-                    MeshGen::generate_chunk_mesh(&mut _vbuf.0, chunk);
+                    if lod == 0 {
+                        MeshGen::generate_chunk_mesh(&mut _vbuf.0, &chunk);
 
-                    MeshGen::generate_chunk_mesh_foliage(&mut _vbuf.1, chunk);
+                        MeshGen::generate_chunk_mesh_foliage(&mut _vbuf.1, &chunk, &models);
+                    } else {
+                        // far chunks. no foliage and collider.
+                        MeshGen::generate_chunk_mesh_lod(&mut _vbuf.0, &chunk, lod);
+                    }
+                    // fluids are full resolution in any LOD, they are cheap. e.g. the sea.
+                    MeshGen::generate_chunk_mesh_fluid(&mut _vbuf.2, &chunk);
 
                     entity = chunk.entity;
                     mesh_handle = chunk.mesh_handle.clone();
                     mesh_handle_foliage = chunk.mesh_handle_foliage.clone();
+                    mesh_handle_fluid = chunk.mesh_handle_fluid.clone();
                 }
                 // let dbg_time = Instant::now() - dbg_time;
 
                 // vbuf.compute_flat_normals();
-                _vbuf.0.compute_smooth_normals();
+                if lod == 0 {
+                    _vbuf.0.compute_smooth_normals();
+                }
 
                 // let nv = vbuf.vertices.len();
-                // vbuf.compute_indexed();  // save 70%+ vertex data space!
-                // todo: Cannot use Real IndexedBuffer, it caused WGSL @builtin(vertex_index) produce invalid Barycentric Coordinate, fails material interpolation.
-                // vulkan also have this issue, but extension
-                //   #extension GL_EXT_fragment_shader_barycentric : enable
-                //   layout(location = 2) pervertexEXT in int in_MtlIds[];  gl_BaryCoordEXT
-                // would fix the problem in vulkan.
-                _vbuf.0.compute_indexed_naive();
+                // Real IndexedBuffer, saves vertex data space. the barycentric coordinate for material interpolation is from
+                // the explicit corner attribute of vertices, instead of WGSL @builtin(vertex_index) which requires non-indexed vertices.
+                _vbuf.0.compute_indexed();
 
                 // if nv != 0 {
                 //     info!("Generated ReMesh verts: {} before: {} after {}, saved: {}%",
@@ -254,11 +414,31 @@ fn chunks_remesh_enqueue(
                 _vbuf.1.to_mesh(&mut mesh_foliage);
                 _vbuf.1.clear();
 
-                // Build Collider of TriMesh
-                let collider = Collider::trimesh_from_mesh(&mesh);
+                // Fluid
+                _vbuf.2.compute_indexed_naive();
+
+                let mut mesh_fluid = Mesh::new(
+                    PrimitiveTopology::TriangleList,
+                    RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+                );
+                _vbuf.2.to_mesh(&mut mesh_fluid);
+                _vbuf.2.clear();
 
-                tx.send((chunkpos, entity, mesh, mesh_handle, collider, mesh_foliage, mesh_handle_foliage))
-                    .unwrap();
+                // Build Collider of TriMesh
+                let collider = if lod == 0 { Collider::trimesh_from_mesh(&mesh) } else { None };
+
+                tx.send((
+                    chunkpos,
+                    entity,
+                    mesh,
+                    mesh_handle,
+                    collider,
+                    mesh_foliage,
+                    mesh_handle_foliage,
+                    mesh_fluid,
+                    mesh_handle_fluid,
+                ))
+                .unwrap();
             });
             task.detach();
 
@@ -270,17 +450,28 @@ fn chunks_remesh_enqueue(
         chunk_sys.chunks_remesh.remove(&chunkpos);
     }
 
-    while let Ok((chunkpos, entity, mesh, mesh_handle, collider, mesh_foliage, mesh_handle_foliage)) = rx_chunks_meshing.try_recv() {
+    while let Ok((chunkpos, entity, mesh, mesh_handle, collider, mesh_foliage, mesh_handle_foliage, mesh_fluid, mesh_handle_fluid)) =
+        rx_chunks_meshing.try_recv()
+    {
+        // a chunk under the sea may have only the fluid.
+        let is_empty = mesh.count_vertices() == 0 && mesh_fluid.count_vertices() == 0;
+
         // Update Mesh Asset
         *meshes.get_mut(mesh_handle).unwrap() = mesh;
 
         *meshes.get_mut(mesh_handle_foliage).unwrap() = mesh_foliage;
 
-        // Update Phys Collider TriMesh
-        if let Some(collider) = collider {
-            if let Some(mut cmds) = commands.get_entity(entity) {
-                // note: use try_insert cuz the entity may already been unloaded when executing the cmds (?)
-                cmds.remove::<Collider>().try_insert(collider).try_insert(Visibility::Visible);
+        *meshes.get_mut(mesh_handle_fluid).unwrap() = mesh_fluid;
+
+        // Update Phys Collider TriMesh. LOD chunks have no collider, the previous one is removed.
+        if let Some(mut cmds) = commands.get_entity(entity) {
+            // note: use try_insert cuz the entity may already been unloaded when executing the cmds (?)
+            cmds.remove::<Collider>();
+            if let Some(collider) = collider {
+                cmds.try_insert(collider);
+            }
+            if !is_empty {
+                cmds.try_insert(Visibility::Visible);
             }
         }
 
@@ -328,7 +519,8 @@ fn raycast(
     mut hit_result: ResMut<HitResult>,
 
     query_input: Query<&ActionState<InputAction>>,
-    mut chunk_sys: ResMut<ClientChunkSystem>,
+    chunk_sys: Res<ClientChunkSystem>,
+    mut net_client: ResMut<RenetClient>,
     cli: Res<ClientInfo>,
     vox_brush: Res<VoxelBrush>,
 ) {
@@ -374,15 +566,16 @@ fn raycast(
         let brush = &*vox_brush;
         let n = brush.size as i32;
 
-        // These code is Horrible
+        // Modifications are not applied locally. they are sent to the server, and applied when the server broadcast them back.
+        let mut modifies = HashMap::<IVec3, Vec<CellData>>::new();
 
         iter::iter_aabb(n, n, |lp| {
             // +0.01*norm: for placing cube like MC.
             let p = hit_result.voxel_pos + lp + 
                 if do_place {1} else {0} * hit_result.normal.normalize_or_zero().as_ivec3();
 
-            if let Some(v) = chunk_sys.get_voxel(p) {
-                let v = v.as_ref_mut();
+            if let Some(old) = chunk_sys.get_cell(p) {
+                let mut v = old;
                 let f = (n as f32 - lp.as_vec3().length()).max(0.) * brush.strength;
 
                 v.set_isovalue(v.isovalue() + if do_break { -f } else { f });
@@ -394,6 +587,14 @@ fn raycast(
                         v.tex_id = brush.tex;
                         v.shape_id = brush.shape;
 
+                        // models face the player.
+                        if let VoxShape::CustomMesh { mesh_id, .. } = brush.shape {
+                            v.shape_id = VoxShape::CustomMesh {
+                                mesh_id,
+                                rotation: facing_rotation(-ray_dir.xz()),
+                            };
+                        }
+
                         // placing Block
                         if brush.shape != VoxShape::Isosurface {
                             v.set_isovalue(0.0);
@@ -403,16 +604,34 @@ fn raycast(
                     }
                 }
 
-                chunk_sys.mark_chunk_remesh(Chunk::as_chunkpos(p)); // CLIS
+                // unchanged cells e.g. the corners of the brush are not sent.
+                if v == old {
+                    return;
+                }
+                let chunkpos = Chunk::as_chunkpos(p);
+                let local_idx = Chunk::local_idx(Chunk::as_localpos(p)) as u16;
+                modifies.entry(chunkpos).or_default().push(CellData::from_cell(local_idx, &v));
             }
         });
-        // let mut map = HashMap::new();
-        // let pack = map.entry(chunkpos).or_insert_with(Vec::new);
-        // pack.push(CellData::from_cell(Chunk::local_idx(Chunk::as_localpos(p)) as u16, &c));
-        // info!("Modify terrain sent {}", map.len());
-        // for e in map {
-        //     net_client.send_packet(&CPacket::ChunkModify { chunkpos: e.0, voxel: e.1 });
-        // }
+
+        for (chunkpos, voxel) in modifies {
+            net_client.send_packet(&CPacket::ChunkModify { chunkpos, voxel });
+        }
+    }
+}
+
+// rotation of a model, turns its front (+Z) to the nearest axis of the direction on XZ.
+fn facing_rotation(dir: Vec2) -> u8 {
+    if dir.x.abs() > dir.y.abs() {
+        if dir.x < 0.0 {
+            1
+        } else {
+            3
+        }
+    } else if dir.y > 0.0 {
+        0
+    } else {
+        2
     }
 }
 
@@ -491,11 +710,17 @@ pub struct ClientChunkSystem {
 
     pub mtl_terrain: Handle<TerrainMaterial>,
     pub mtl_foliage: Handle<StandardMaterial>,
+    pub mtl_fluid: Handle<StandardMaterial>,
     pub entity: Entity,
 
     pub max_concurrent_meshing: usize,
     pub chunks_meshing: HashSet<IVec3>,
+    // LOD of chunks when they are meshed. see `chunk_lod`.
+    pub chunks_lod: HashMap<IVec3, u8>,
     // pub chunks_load_distance: IVec2, // not real, but send to server,
+
+    // generates chunks locally before the server's arrive. None until the world seed is received on login.
+    pub worldgen: Option<Arc<WorldGen>>,
 }
 
 impl ChunkSystem for ClientChunkSystem {
@@ -518,10 +743,14 @@ impl ClientChunkSystem {
 
             mtl_terrain: Handle::default(),
             mtl_foliage: Handle::default(),
+            mtl_fluid: Handle::default(),
             entity: Entity::PLACEHOLDER,
 
             max_concurrent_meshing: 8,
             chunks_meshing: HashSet::default(),
+            chunks_lod: HashMap::default(),
+
+            worldgen: None,
         }
     }
 
@@ -536,6 +765,7 @@ impl ClientChunkSystem {
 
         chunk.mesh_handle = meshes.add(Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::MAIN_WORLD));
         chunk.mesh_handle_foliage = meshes.add(Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::MAIN_WORLD));
+        chunk.mesh_handle_fluid = meshes.add(Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::MAIN_WORLD));
 
         chunk.entity = cmds
             .spawn((
@@ -560,54 +790,56 @@ impl ClientChunkSystem {
                     },
                     aabb,
                 ));
+                parent.spawn((
+                    MaterialMeshBundle {
+                        mesh: chunk.mesh_handle_fluid.clone(),
+                        material: self.mtl_fluid.clone(),
+                        ..default()
+                    },
+                    aabb,
+                    NotShadowCaster,
+                ));
             })
             .set_parent(self.entity)
             .id();
 
-        let chunkptr = Arc::new(chunk);
-
-        let chunkpos;
-        {
-            let chunk = chunkptr.as_ref_mut();
-            chunkpos = chunk.chunkpos;
-
-            let mut neighbors_nearby_completed = Vec::new();
-
-            for neib_idx in 0..Chunk::NEIGHBOR_DIR.len() {
-                let neib_dir = Chunk::NEIGHBOR_DIR[neib_idx];
-                let neib_chunkpos = chunkpos + neib_dir * Chunk::SIZE;
-
-                // todo: delay remesh or only remesh full-neighbor complete chunks
+        // set neighbor_chunks cache, before the chunk is shared.
+        for neib_idx in 0..Chunk::NEIGHBOR_DIR.len() {
+            let neib_chunkpos = chunkpos + Chunk::NEIGHBOR_DIR[neib_idx] * Chunk::SIZE;
+            chunk.neighbor_chunks[neib_idx] = self.get_chunk(neib_chunkpos).map(Arc::downgrade);
+        }
 
-                // set neighbor_chunks cache
-                chunk.neighbor_chunks[neib_idx] = {
-                    if let Some(neib_chunkptr) = self.get_chunk(neib_chunkpos) {
-                        let neib_chunk = neib_chunkptr.as_ref_mut();
+        let chunkptr = Arc::new(RwLock::new(chunk));
 
-                        // update neighbor's `neighbor_chunk`
-                        neib_chunk.neighbor_chunks[Chunk::neighbor_idx_opposite(neib_idx)] = Some(Arc::downgrade(&chunkptr));
+        // update neighbor's `neighbor_chunk`. only lock one chunk at a time, meshing tasks may be reading them.
+        let mut neighbors_nearby_completed = Vec::new();
+        for neib_idx in 0..Chunk::NEIGHBOR_DIR.len() {
+            let neib_chunkpos = chunkpos + Chunk::NEIGHBOR_DIR[neib_idx] * Chunk::SIZE;
 
-                        if neib_chunk.is_neighbors_complete() {
-                            neighbors_nearby_completed.push(neib_chunk.chunkpos);
-                        }
+            // todo: delay remesh or only remesh full-neighbor complete chunks
+            if let Some(neib_chunkptr) = self.get_chunk(neib_chunkpos) {
+                let mut neib_chunk = neib_chunkptr.write().unwrap();
+                neib_chunk.neighbor_chunks[Chunk::neighbor_idx_opposite(neib_idx)] = Some(Arc::downgrade(&chunkptr));
 
-                        Some(Arc::downgrade(neib_chunkptr))
-                    } else {
-                        None
-                    }
+                if neib_chunk.is_neighbors_complete() {
+                    neighbors_nearby_completed.push(neib_chunkpos);
                 }
             }
+        }
 
-            // if chunk.is_neighbors_complete() {
-            self.mark_chunk_remesh(chunkpos);
-            // }
-            for cp in neighbors_nearby_completed {
-                self.mark_chunk_remesh(cp);
-            }
+        // if chunk.is_neighbors_complete() {
+        self.mark_chunk_remesh(chunkpos);
+        // }
+        for cp in neighbors_nearby_completed {
+            self.mark_chunk_remesh(cp);
         }
 
         self.chunks.insert(chunkpos, chunkptr);
 
+        for cp in lighting::light_new_chunk(self, chunkpos) {
+            self.mark_chunk_remesh(cp);
+        }
+
         // // There is no need to cast shadows for chunks below the surface.
         // if chunkpos.y <= 64 {
         //     entity_commands.insert(NotShadowCaster);
@@ -618,6 +850,7 @@ impl ClientChunkSystem {
 
     pub fn despawn_chunk(&mut self, chunkpos: IVec3) -> Option<ChunkPtr> {
         let chunk = self.chunks.remove(&chunkpos)?;
+        self.chunks_lod.remove(&chunkpos);
 
         //cmds.entity(chunk.entity).despawn_recursive();
 
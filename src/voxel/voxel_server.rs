@@ -3,18 +3,23 @@ use bevy::{
     tasks::AsyncComputeTaskPool,
     utils::{HashMap, HashSet},
 };
+use bevy_common_assets::json::JsonAssetPlugin;
 use bevy_renet::renet::RenetServer;
 use bevy_xpbd_3d::components::RigidBody;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
-use super::{ChannelRx, ChannelTx, Chunk, ChunkPtr, ChunkSystem, WorldGen};
+use super::{
+    BlockBehaviors, BlockUpdates, Cell, ChannelRx, ChannelTx, Chunk, ChunkLoader, ChunkPtr, ChunkSystem, FluidSim, WorldGen, WorldGenPreset,
+    WorldGenPresets, WorldMeta,
+};
 use crate::{
     net::{CellData, RenetServerHelper, SPacket},
-    server::prelude::ServerInfo,
-    util::{iter, AsRefMut},
+    server::prelude::{ServerInfo, ServerSettings},
+    util::{current_timestamp_millis, iter, TimeIntervals},
 };
 
-type ChunkLoadingData = (IVec3, ChunkPtr);
+// (world_id, chunkpos, chunk)
+type ChunkLoadingData = (u32, IVec3, ChunkPtr);
 
 pub struct ServerVoxelPlugin;
 
@@ -28,8 +33,104 @@ impl Plugin for ServerVoxelPlugin {
             app.insert_resource(ChannelRx(rx));
         }
 
-        app.add_systems(Update, chunks_load);
+        app.add_plugins(JsonAssetPlugin::<WorldGenPreset>::new(&["worldgen.json"]));
+        app.add_systems(Startup, load_presets);
+
+        app.add_systems(Update, (on_world_init, chunks_load, fluids_tick, blocks_tick, chunks_autosave).chain());
+        app.add_systems(Last, on_world_exit);
+    }
+}
+
+fn load_presets(mut cmds: Commands, asset_server: Res<AssetServer>) {
+    cmds.insert_resource(WorldGenPresets::load(&asset_server));
+}
+
+// (Re)Open the world save when `world_dir` is changed. e.g. the integrated server switching to a newly created world.
+// A new world waits until its worldgen preset is loaded.
+fn on_world_init(
+    mut chunk_sys: ResMut<ServerChunkSystem>,
+    cfg: Res<ServerSettings>,
+    presets: Res<WorldGenPresets>,
+    preset_assets: Res<Assets<WorldGenPreset>>,
+    asset_server: Res<AssetServer>,
+    mut opened_dir: Local<Option<String>>,
+    mut cmds: Commands,
+) {
+    if opened_dir.as_ref() == Some(&cfg.world_dir) {
+        return;
+    }
+    let chunk_loader = Arc::new(ChunkLoader::new(&cfg.world_dir));
+
+    let meta = match chunk_loader.load_meta() {
+        Ok(Some(meta)) => meta,
+        result => {
+            let Some(preset) = presets.get(&cfg.world_preset, &preset_assets, &asset_server) else {
+                return;
+            };
+            let meta = WorldMeta {
+                seed: WorldGen::parse_seed(&cfg.world_seed),
+                time_created: current_timestamp_millis(),
+                preset,
+            };
+            match result {
+                Err(err) => {
+                    // don't overwrite the broken meta, the seed may be recovered manually.
+                    error!("Failed to load world meta: {}", err);
+                }
+                _ => {
+                    info!("Create world with preset '{}'", cfg.world_preset);
+                    if let Err(err) = chunk_loader.save_meta(&meta) {
+                        error!("Failed to save world meta: {}", err);
+                    }
+                }
+            }
+            meta
+        }
+    };
+
+    if opened_dir.is_some() {
+        chunk_sys.close_world(&mut cmds);
+    }
+    *opened_dir = Some(cfg.world_dir.clone());
+
+    info!("Open world save at {}", cfg.world_dir);
+    info!("World seed: {}", meta.seed);
+
+    let worldgen = WorldGen::with_preset(meta.seed, meta.preset);
+    match chunk_loader.load_pending_writes() {
+        Ok(pending_writes) => *worldgen.pending_writes.lock().unwrap() = pending_writes,
+        Err(err) => error!("Failed to load pending writes: {}", err),
+    }
+
+    chunk_sys.world_id += 1;
+    chunk_sys.chunk_loader = chunk_loader;
+    chunk_sys.worldgen = Arc::new(worldgen);
+    chunk_sys.block_updates = BlockUpdates::new(meta.seed);
+}
+
+// Save all dirty chunks on exit.
+fn on_world_exit(mut exit_events: EventReader<bevy::app::AppExit>, chunk_sys: Res<ServerChunkSystem>) {
+    for _ in exit_events.read() {
+        let num_saved = chunk_sys.save_dirty_chunks();
+        info!("Saving {} dirty chunks", num_saved);
+        chunk_sys.save_pending_writes();
+
+        if let Err(err) = chunk_sys.chunk_loader.flush() {
+            error!("Failed to flush region files: {}", err);
+        }
+    }
+}
+
+// Periodically flush modified chunks. chunks are encoded here and written to region files in background.
+fn chunks_autosave(chunk_sys: Res<ServerChunkSystem>, cfg: Res<ServerSettings>, time: Res<Time>) {
+    if cfg.autosave_interval <= 0.0 || !time.at_interval(cfg.autosave_interval) {
+        return;
+    }
+    let num_saved = chunk_sys.save_dirty_chunks();
+    if num_saved != 0 {
+        info!("Autosaved {} dirty chunks", num_saved);
     }
+    chunk_sys.save_pending_writes();
 }
 
 fn in_load_distance(mid_cp: IVec3, cp: IVec3, vd: IVec2) -> bool {
@@ -42,7 +143,6 @@ fn chunks_load(
     mut server: ResMut<ServerInfo>,
     mut cmds: Commands,
 
-    mut chunks_loading: Local<HashSet<IVec3>>, // for detect/skip if is loading
     tx_chunks_loading: Res<ChannelTx<ChunkLoadingData>>,
     rx_chunks_loading: Res<ChannelRx<ChunkLoadingData>>,
 ) {
@@ -51,60 +151,95 @@ fn chunks_load(
     // 待改进: 这里可能有多种加载方法，包括Inner-Outer近距离优先加载，填充IVec3待加载列表并排序方法
 
     // Dispatch Chunk Load
+    let chunk_loader = chunk_sys.chunk_loader.clone();
+    let worldgen = chunk_sys.worldgen.clone();
+    let world_id = chunk_sys.world_id;
     for player in server.online_players.values() {
         let vd = player.chunks_load_distance;
         let cp = Chunk::as_chunkpos(player.position.as_ivec3());
 
         iter::iter_center_spread(vd.x, vd.y, |rp| {
             let chunkpos = rp * Chunk::SIZE + cp;
-            if chunks_loading.len() > 8 {
+            if chunk_sys.chunks_loading.len() > 8 {
                 // max_concurrent_loading_chunks
                 return;
             }
-            if chunk_sys.has_chunk(chunkpos) || chunks_loading.contains(&chunkpos) {
+            if chunk_sys.has_chunk(chunkpos) || chunk_sys.chunks_loading.contains(&chunkpos) {
                 return;
             }
 
             let tx = tx_chunks_loading.clone();
+            let chunk_loader = chunk_loader.clone();
+            let worldgen = worldgen.clone();
             let task = AsyncComputeTaskPool::get().spawn(async move {
                 // info!("Load Chunk: {:?}", chunkpos);
                 let mut chunk = Chunk::new(chunkpos);
 
-                WorldGen::generate_chunk(&mut chunk);
-
-                let chunkptr = Arc::new(chunk);
-                tx.send((chunkpos, chunkptr)).unwrap();
+                // Load from the world save, or generate it if it has never been saved.
+                let deferred = match chunk_loader.load_chunk(&mut chunk) {
+                    Ok(true) => false,
+                    Ok(false) => worldgen.generate_chunk(&mut chunk),
+                    Err(err) => {
+                        error!("Failed to load chunk {}: {}", chunkpos, err);
+                        worldgen.generate_chunk(&mut chunk)
+                    }
+                };
+                // freshly loaded/generated chunk is identical to the save (or reproducible from worldgen), not dirty.
+                // unless it deferred feature cells to neighbors, it's saved once, not to place them again when regenerated.
+                if !deferred {
+                    chunk.mark_saved();
+                }
+
+                // features placed by neighbor chunks generated earlier. not reproducible, makes the chunk dirty.
+                worldgen.apply_pending_writes(&mut chunk);
+
+                let chunkptr = Arc::new(RwLock::new(chunk));
+                tx.send((world_id, chunkpos, chunkptr)).unwrap();
             });
 
             task.detach();
-            chunks_loading.insert(chunkpos);
+            chunk_sys.chunks_loading.insert(chunkpos);
 
             info!("ChunkLoad Enqueue {} / {}", chunk_sys.num_chunks(), chunkpos);
         });
     }
 
     // Complete Chunk Load
-    while let Ok((chunkpos, chunkptr)) = rx_chunks_loading.try_recv() {
-        chunks_loading.remove(&chunkpos);
-
-        {
-            let chunk = chunkptr.as_ref_mut();
-
-            chunk.entity = cmds
-                .spawn((
-                    // ChunkComponent::new(chunkpos),
-                    Transform::from_translation(chunkpos.as_vec3()),
-                    GlobalTransform::IDENTITY, // really?
-                    RigidBody::Static,
-                ))
-                .id();
+    let mut any_loaded = false;
+    while let Ok((world_id, chunkpos, chunkptr)) = rx_chunks_loading.try_recv() {
+        // loaded from the world closed since, by the old chunk loader and worldgen.
+        if world_id != chunk_sys.world_id {
+            continue;
         }
+        any_loaded = true;
+        chunk_sys.chunks_loading.remove(&chunkpos);
+
+        chunkptr.write().unwrap().entity = cmds
+            .spawn((
+                // ChunkComponent::new(chunkpos),
+                Transform::from_translation(chunkpos.as_vec3()),
+                GlobalTransform::IDENTITY, // really?
+                RigidBody::Static,
+            ))
+            .id();
 
         chunk_sys.spawn_chunk(chunkptr);
 
         info!("ChunkLoad Completed {} / {}", chunk_sys.num_chunks(), chunkpos);
     }
 
+    // Features placed into already loaded chunks by the newly generated chunks.
+    if any_loaded {
+        let written = chunk_sys.worldgen.apply_pending_writes_loaded(&chunk_sys.chunks);
+        for (chunkpos, cells) in written {
+            for (local_idx, _) in cells.iter() {
+                chunk_sys.notify_changed(chunkpos + Chunk::local_idx_pos(*local_idx as i32));
+            }
+            let voxel = cells.iter().map(|(local_idx, cell)| CellData::from_cell(*local_idx, cell)).collect();
+            send_chunk_modify(&mut net_server, &server, chunkpos, voxel);
+        }
+    }
+
     // Unload Chunks
     // 野蛮区块卸载检测
     let chunkpos_all = Vec::from_iter(chunk_sys.get_chunks().keys().cloned());
@@ -118,8 +253,9 @@ fn chunks_load(
         }
 
         if !any_desire {
-            let chunkptr = chunk_sys.despawn_chunk(chunkpos);
-            let entity = chunkptr.unwrap().as_ref().entity;
+            let chunkptr = chunk_sys.despawn_chunk(chunkpos).unwrap();
+            chunk_sys.save_chunk_if_dirty(&chunkptr);
+            let entity = chunkptr.read().unwrap().entity;
             cmds.entity(entity).despawn_recursive();
 
             net_server.broadcast_packet(&SPacket::ChunkDel { chunkpos });
@@ -160,16 +296,97 @@ fn chunks_load(
                     num_sent,
                     player.username
                 );
-                let data = CellData::from_chunk(chunkptr.as_ref());
+                let data = chunkptr.read().unwrap().cells().clone();
                 net_server.send_packet(player.client_id, &SPacket::ChunkNew { chunkpos, voxel: data });
             }
         });
     }
 }
 
+// Fixed steps of the fluid simulation. steps missed by a long frame are caught up, up to a few.
+fn fluids_tick(
+    mut chunk_sys: ResMut<ServerChunkSystem>,
+    mut net_server: ResMut<RenetServer>,
+    server: Res<ServerInfo>,
+    cfg: Res<ServerSettings>,
+    time: Res<Time>,
+) {
+    if cfg.fluid_tick_interval <= 0.0 {
+        return;
+    }
+    let num_ticks = time.intervals(cfg.fluid_tick_interval).min(4);
+
+    // batch cells of all steps into one packet per chunk.
+    let mut modified: HashMap<IVec3, Vec<CellData>> = HashMap::default();
+    for _ in 0..num_ticks {
+        batch_modified(&mut modified, chunk_sys.tick_fluids(cfg.fluid_tick_budget));
+    }
+    for (chunkpos, voxel) in modified {
+        send_chunk_modify(&mut net_server, &server, chunkpos, voxel);
+    }
+}
+
+// Fixed steps of block updates, scheduled ticks and random ticks. see `BlockUpdates`.
+fn blocks_tick(
+    mut chunk_sys: ResMut<ServerChunkSystem>,
+    mut net_server: ResMut<RenetServer>,
+    server: Res<ServerInfo>,
+    cfg: Res<ServerSettings>,
+    time: Res<Time>,
+) {
+    if cfg.block_tick_interval <= 0.0 {
+        return;
+    }
+    let num_ticks = time.intervals(cfg.block_tick_interval).min(4);
+
+    let mut modified: HashMap<IVec3, Vec<CellData>> = HashMap::default();
+    for _ in 0..num_ticks {
+        batch_modified(&mut modified, chunk_sys.tick_blocks(cfg.random_ticks_per_chunk));
+    }
+    for (chunkpos, voxel) in modified {
+        send_chunk_modify(&mut net_server, &server, chunkpos, voxel);
+    }
+}
+
+fn batch_modified(modified: &mut HashMap<IVec3, Vec<CellData>>, cells_of_chunks: Vec<(IVec3, Vec<(u16, Cell)>)>) {
+    for (chunkpos, cells) in cells_of_chunks {
+        let voxel = modified.entry(chunkpos).or_default();
+        voxel.extend(cells.iter().map(|(local_idx, cell)| CellData::from_cell(*local_idx, cell)));
+    }
+}
+
+// to every player who has the chunk.
+fn send_chunk_modify(net_server: &mut RenetServer, server: &ServerInfo, chunkpos: IVec3, voxel: Vec<CellData>) {
+    let packet = SPacket::ChunkModify { chunkpos, voxel };
+    for player in server.online_players.values() {
+        if player.chunks_loaded.contains(&chunkpos) {
+            net_server.send_packet(player.client_id, &packet);
+        }
+    }
+}
+
 #[derive(Resource)]
 pub struct ServerChunkSystem {
     pub chunks: HashMap<IVec3, ChunkPtr>,
+
+    // Region files of the world save. shared with async chunk loading tasks.
+    pub chunk_loader: Arc<ChunkLoader>,
+
+    // generator of the opened world, seeded from the world meta.
+    pub worldgen: Arc<WorldGen>,
+
+    // schedule cells here after modifying chunks, to let fluids flow.
+    pub fluids: FluidSim,
+
+    // see `notify_changed`.
+    pub block_updates: BlockUpdates,
+    pub block_behaviors: BlockBehaviors,
+
+    // incremented when a world is opened. chunks loaded by tasks of a closed world are dropped.
+    world_id: u32,
+
+    // chunks being loaded by async tasks, of the opened world.
+    chunks_loading: HashSet<IVec3>,
 }
 
 impl ChunkSystem for ServerChunkSystem {
@@ -180,15 +397,94 @@ impl ChunkSystem for ServerChunkSystem {
 
 impl ServerChunkSystem {
     fn new() -> Self {
-        Self { chunks: HashMap::default() }
+        Self {
+            chunks: HashMap::default(),
+            chunk_loader: Arc::new(ChunkLoader::new(ServerSettings::default().world_dir)),
+            worldgen: Arc::new(WorldGen::new(0)),
+            fluids: FluidSim::default(),
+            block_updates: BlockUpdates::new(0),
+            block_behaviors: BlockBehaviors::with_defaults(),
+            world_id: 0,
+            chunks_loading: HashSet::default(),
+        }
+    }
+
+    // Save and unload all chunks of the opened world.
+    fn close_world(&mut self, cmds: &mut Commands) {
+        let num_saved = self.save_dirty_chunks();
+        info!("Close world, saving {} dirty chunks", num_saved);
+        self.save_pending_writes();
+        if let Err(err) = self.chunk_loader.flush() {
+            error!("Failed to flush region files: {}", err);
+        }
+
+        self.fluids.clear();
+        self.block_updates.clear();
+        self.chunks_loading.clear();
+        for (_, chunkptr) in self.chunks.drain() {
+            if let Some(cmds) = cmds.get_entity(chunkptr.read().unwrap().entity) {
+                cmds.despawn_recursive();
+            }
+        }
     }
 
     fn spawn_chunk(&mut self, chunkptr: ChunkPtr) {
-        let cp = chunkptr.as_ref().chunkpos;
+        let cp = chunkptr.read().unwrap().chunkpos;
         self.chunks.insert(cp, chunkptr);
+        self.fluids.on_chunk_loaded(&self.chunks, cp);
     }
 
     fn despawn_chunk(&mut self, chunkpos: IVec3) -> Option<ChunkPtr> {
         self.chunks.remove(&chunkpos)
     }
+
+    /// Call after the cell is modified, let fluids flow and neighbors react, e.g. sand falls.
+    pub fn notify_changed(&mut self, p: IVec3) {
+        self.fluids.schedule_around(p);
+        self.block_updates.notify_changed(p);
+    }
+
+    /// Step the fluid simulation, updating at most `budget` cells. return the changed cells of each chunk.
+    pub fn tick_fluids(&mut self, budget: usize) -> Vec<(IVec3, Vec<(u16, Cell)>)> {
+        let modified = self.fluids.tick(&self.chunks, budget);
+        for (chunkpos, cells) in modified.iter() {
+            for (local_idx, _) in cells.iter() {
+                self.block_updates.notify_changed(*chunkpos + Chunk::local_idx_pos(*local_idx as i32));
+            }
+        }
+        modified
+    }
+
+    /// Step block updates and ticks of cells. return the changed cells of each chunk.
+    pub fn tick_blocks(&mut self, random_ticks_per_chunk: u32) -> Vec<(IVec3, Vec<(u16, Cell)>)> {
+        let modified = self.block_updates.tick(&self.chunks, &self.block_behaviors, random_ticks_per_chunk);
+        for (chunkpos, cells) in modified.iter() {
+            for (local_idx, _) in cells.iter() {
+                self.fluids.schedule_around(*chunkpos + Chunk::local_idx_pos(*local_idx as i32));
+            }
+        }
+        modified
+    }
+
+    /// Save the chunk in background if it has been modified since last save.
+    pub fn save_chunk_if_dirty(&self, chunkptr: &ChunkPtr) -> bool {
+        let mut chunk = chunkptr.write().unwrap();
+        if !chunk.is_dirty() {
+            return false;
+        }
+        self.chunk_loader.save_chunk_async(&chunk);
+        chunk.mark_saved();
+        true
+    }
+
+    /// Save pending writes of worldgen in background.
+    pub fn save_pending_writes(&self) {
+        let pending_writes = self.worldgen.pending_writes.lock().unwrap().clone();
+        self.chunk_loader.save_pending_writes_async(pending_writes);
+    }
+
+    /// return number of chunks saved.
+    pub fn save_dirty_chunks(&self) -> usize {
+        self.chunks.values().filter(|chunkptr| self.save_chunk_if_dirty(chunkptr)).count()
+    }
 }
@@ -1,59 +1,330 @@
-use std::ops::Div;
+use std::{ops::Div, sync::Mutex};
 
-use bevy::{math::ivec3, prelude::*};
+use bevy::{math::ivec3, prelude::*, utils::HashMap};
 
 use crate::util::{hash, iter};
 
-use super::chunk::*;
+use super::{chunk::*, ChunkPtr};
 
 use noise::{Fbm, NoiseFn, Perlin};
 
-pub struct WorldGen {}
-use super::material::mtl;
+use super::{
+    biome::{biome_weights, Biome, Climate, TreeKind, BIOMES},
+    carver::CaveCarver,
+    material::mtl,
+    ore::place_ores,
+    preset::{TerrainKind, WorldGenPreset},
+    structure::StructureGen,
+};
+
+/// Terrain parameters of a world column, blended from nearby biomes.
+#[derive(Clone, Copy, Debug)]
+pub struct ColumnInfo {
+    pub biome: Biome,
+    pub height_offset: f32,
+    pub height_scale: f32,
+    pub water_level: f32,
+}
+
+/// Feature cells placed outside of their generating chunk, keyed by the target chunkpos. (local_idx, cell)
+pub type PendingWrites = HashMap<IVec3, Vec<(u16, Cell)>>;
+
+/// Seeded World Generator. constructed once per world, and shared with async chunk generation tasks.
+/// The same seed always generates the same terrain.
+pub struct WorldGen {
+    pub seed: u64,
+    pub preset: WorldGenPreset,
+
+    height_noise: Fbm<Perlin>,
+    detail_noise: Fbm<Perlin>,
+    perlin: Perlin,
+
+    // Climate noises of the biome layer
+    temperature: Perlin,
+    humidity: Perlin,
+    continentalness: Fbm<Perlin>,
+
+    carver: CaveCarver,
+    structures: StructureGen,
+
+    // mixed into the position hash of features (grass, vines, trees)
+    feature_seed: i32,
+
+    /// Feature cells placed outside of their generating chunk.
+    /// applied when the target chunk is generated/loaded, or directly if it's already loaded.
+    pub pending_writes: Mutex<PendingWrites>,
+}
 
 impl WorldGen {
-    pub fn generate_chunk(chunk: &mut Chunk) {
-        let seed = 100;
-        // let perlin = Perlin::new(seed);
-        let mut fbm = Fbm::<Perlin>::new(seed);
-        // fbm.frequency = 0.2;
-        // fbm.lacunarity = 0.2;
-        fbm.octaves = 5;
-        // fbm.persistence = 2;
+    pub fn new(seed: u64) -> Self {
+        Self::with_preset(seed, WorldGenPreset::default())
+    }
+
+    pub fn with_preset(seed: u64, preset: WorldGenPreset) -> Self {
+        // noise functions takes u32 seeds. (seed 100 generates the original terrain)
+        let noise_seed = (seed ^ (seed >> 32)) as u32;
+
+        let mut height_noise = Fbm::<Perlin>::new(noise_seed);
+        height_noise.octaves = preset.terrain.height_noise.octaves;
+        let mut detail_noise = Fbm::<Perlin>::new(noise_seed);
+        detail_noise.octaves = preset.terrain.detail_noise.octaves;
+
+        let mut continentalness = Fbm::<Perlin>::new(noise_seed.wrapping_add(3));
+        continentalness.octaves = 3;
+
+        Self {
+            seed,
+            height_noise,
+            detail_noise,
+            perlin: Perlin::new(noise_seed.wrapping_add(23)),
+            temperature: Perlin::new(noise_seed.wrapping_add(1)),
+            humidity: Perlin::new(noise_seed.wrapping_add(2)),
+            continentalness,
+            carver: CaveCarver::new(seed, noise_seed, preset.caves.clone()),
+            structures: StructureGen::new(seed, preset.structures.clone()),
+            feature_seed: noise_seed.wrapping_sub(100) as i32,
+            pending_writes: Mutex::default(),
+            preset,
+        }
+    }
+
+    /// Parse the Seed text of a world. integers are used as they are, other text is hashed. empty for a random seed.
+    pub fn parse_seed(text: &str) -> u64 {
+        let text = text.trim();
+        if text.is_empty() {
+            return rand::random();
+        }
+        if let Ok(seed) = text.parse::<i64>() {
+            return seed as u64;
+        }
+        // FNV-1a. stable across platforms and versions, unlike std DefaultHasher.
+        text.bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+    }
+
+    pub fn climate_at(&self, x: i32, z: i32) -> Climate {
+        let cfg = &self.preset.terrain;
+        let x = x as f64;
+        let z = z as f64;
+        Climate {
+            temperature: (self.temperature.get([x / cfg.temperature_scale, z / cfg.temperature_scale]) as f32 * 1.6).clamp(-1.0, 1.0),
+            humidity: (self.humidity.get([x / cfg.humidity_scale, z / cfg.humidity_scale]) as f32 * 1.6).clamp(-1.0, 1.0),
+            continentalness: (self.continentalness.get([x / cfg.continentalness_scale, z / cfg.continentalness_scale]) as f32
+                + cfg.continentalness_bias)
+                .clamp(-1.0, 1.0),
+        }
+    }
+
+    /// Terrain parameters are blended by biome weights, thus no cliffs at biome borders.
+    /// The biome itself is picked by the weights with a blotchy noise, so surface materials interleave at borders.
+    pub fn column_at(&self, x: i32, z: i32) -> ColumnInfo {
+        let sea_level = self.preset.terrain.sea_level;
+        if let Some(biome) = self.preset.terrain.biome {
+            let def = biome.def();
+            return ColumnInfo {
+                biome,
+                height_offset: def.height_offset,
+                height_scale: def.height_scale,
+                water_level: def.water_level + sea_level,
+            };
+        }
+        let weights = biome_weights(&self.climate_at(x, z));
+
+        let mut col = ColumnInfo {
+            biome: Biome::Plains,
+            height_offset: 0.0,
+            height_scale: 0.0,
+            water_level: sea_level,
+        };
+        for (def, w) in BIOMES.iter().zip(weights) {
+            col.height_offset += def.height_offset * w;
+            col.height_scale += def.height_scale * w;
+            col.water_level += def.water_level * w;
+        }
+
+        let mut r = (self.perlin.get([x as f64 / 7.3, z as f64 / 7.3]) as f32 * 0.5 + 0.5).clamp(0.0, 0.999);
+        for (def, w) in BIOMES.iter().zip(weights) {
+            col.biome = def.biome;
+            if r < w {
+                break;
+            }
+            r -= w;
+        }
+        col
+    }
+
+    pub fn biome_at(&self, x: i32, z: i32) -> Biome {
+        self.column_at(x, z).biome
+    }
+
+    /// Generate terrain and features of the chunk. feature cells outside of the chunk are deferred to `pending_writes`.
+    /// Incoming pending writes are not applied here, see `apply_pending_writes`.
+    /// return true if feature cells are deferred to other chunks. regenerating the chunk would defer them again,
+    /// e.g. trees over the area cleared by players, so such chunks should be saved.
+    pub fn generate_chunk(&self, chunk: &mut Chunk) -> bool {
+        let cfg = &self.preset.terrain;
+        if cfg.kind == TerrainKind::Void {
+            Self::generate_void(chunk);
+            return false;
+        }
+
+        let columns: Vec<ColumnInfo> = (0..Chunk::SIZE * Chunk::SIZE)
+            .map(|i| self.column_at(chunk.chunkpos.x + i / Chunk::SIZE, chunk.chunkpos.z + i % Chunk::SIZE))
+            .collect();
+
+        let carve = self.carver.carve_chunk(chunk.chunkpos);
+        let water_guard = self.carver.config.water_guard;
 
         for ly in 0..Chunk::SIZE {
             for lz in 0..Chunk::SIZE {
                 for lx in 0..Chunk::SIZE {
                     let lp = IVec3::new(lx, ly, lz);
                     let p = chunk.chunkpos + lp;
+                    let col = &columns[(lx * Chunk::SIZE + lz) as usize];
 
-                    let f_terr = fbm.get(p.xz().as_dvec2().div(130.).to_array()) as f32;
-                    let f_3d = fbm.get(p.as_dvec3().div(90.).to_array()) as f32;
+                    let mut val = self.terrain_density(p, col);
 
-                    let mut val = f_terr - (p.y as f32) / 18. + f_3d * 4.5;
-                    // val = (-p.y as f32 - 1.) / 18.;  // super flat
-
-                    let mut tex = mtl::NIL; //(p.x / 2 % 24).abs() as u16;
-                    if val > 0.0 {
-                        tex = mtl::STONE;
-                    } else if p.y < 0 && val < 0. {
-                        val = 0.1;
-                        tex = mtl::WATER;
+                    // Caves. carved cells are air even below water level, but a shell is kept under water.
+                    let mut carved = false;
+                    let carve_val = carve[Chunk::local_idx(lp)];
+                    if carve_val < val && ((p.y as f32) >= col.water_level || val > water_guard) {
+                        val = carve_val;
+                        carved = true;
                     }
-                    chunk.set_cell(lp, &Cell::new(tex, VoxShape::Isosurface, val));
+
+                    let cell = if val > 0.0 {
+                        Cell::new(mtl::STONE, VoxShape::Isosurface, val)
+                    } else if (p.y as f32) < col.water_level && val < 0. && !carved {
+                        // water keeps the (empty) isovalue, for the terrain surface under it.
+                        Cell::new_fluid(mtl::WATER, Cell::FLUID_LEVELS, val)
+                    } else {
+                        Cell::new(mtl::NIL, VoxShape::Isosurface, val)
+                    };
+                    chunk.set_cell(lp, &cell);
                 }
             }
         }
 
-        Self::populate_chunk(chunk);
+        let deferred = self.populate_chunk(chunk, &columns);
+
+        // after the surface pass, ores only replace the remaining stone.
+        place_ores(self.seed, &self.preset.ores, chunk);
+
+        // last, structures replace the terrain, features and ores where they are placed.
+        self.structures.write_chunk(chunk, |x, z| self.surface_y(x, z));
+
+        chunk.compact_cells();
+        deferred
+    }
+
+    /// Terrain isovalue before caves are carved. positive is solid.
+    fn terrain_density(&self, p: IVec3, col: &ColumnInfo) -> f32 {
+        let cfg = &self.preset.terrain;
+        if cfg.kind == TerrainKind::Flat {
+            // solid up to y = flat_height - 1, as `surface_y`.
+            return (cfg.flat_height - p.y) as f32 / cfg.height_falloff;
+        }
+        let f_terr = self.height_noise.get(p.xz().as_dvec2().div(cfg.height_noise.scale).to_array()) as f32;
+        let f_3d = self.detail_noise.get(p.as_dvec3().div(cfg.detail_noise.scale).to_array()) as f32;
+
+        f_terr * cfg.height_noise.amplitude * col.height_scale + col.height_offset - (p.y as f32) / cfg.height_falloff
+            + f_3d * cfg.detail_noise.amplitude
+    }
+
+    /// Y of the topmost solid cell of the column, ignoring caves. None if it's under water, or there is no ground.
+    pub fn surface_y(&self, x: i32, z: i32) -> Option<i32> {
+        let col = self.column_at(x, z);
+        let y = match self.preset.terrain.kind {
+            TerrainKind::Void => return None,
+            TerrainKind::Flat => self.preset.terrain.flat_height - 1,
+            TerrainKind::Noise => (-64..160).rev().find(|&y| self.terrain_density(IVec3::new(x, y, z), &col) > 0.0)?,
+        };
+        ((y as f32) >= col.water_level).then_some(y)
     }
 
-    fn populate_chunk(chunk: &mut Chunk) {
+    // Void world. only a platform at the origin to stand on.
+    fn generate_void(chunk: &mut Chunk) {
+        if chunk.chunkpos != IVec3::new(0, -Chunk::SIZE, 0) {
+            return;
+        }
+        for lx in 0..Chunk::SIZE {
+            for lz in 0..Chunk::SIZE {
+                chunk.set_cell(ivec3(lx, Chunk::SIZE - 2, lz), &Cell::new(mtl::STONE, VoxShape::Isosurface, 0.5));
+                chunk.set_cell(ivec3(lx, Chunk::SIZE - 1, lz), &Cell::new(mtl::GRASS, VoxShape::Isosurface, 0.5));
+            }
+        }
+    }
+
+    // return true if any.
+    fn push_pending_writes(&self, writes: PendingWrites) -> bool {
+        if writes.is_empty() {
+            return false;
+        }
+        let mut pending_writes = self.pending_writes.lock().unwrap();
+        for (chunkpos, cells) in writes {
+            // a chunk regenerated before the target is loaded, e.g. on clients, defers the same cells again.
+            let pending = pending_writes.entry(chunkpos).or_default();
+            for c in cells {
+                if !pending.contains(&c) {
+                    pending.push(c);
+                }
+            }
+        }
+        true
+    }
+
+    /// Apply pending feature cells of the chunk. return the changed cells.
+    /// A generated chunk should `mark_saved` before this, the applied cells are not reproducible by regenerating.
+    pub fn apply_pending_writes(&self, chunk: &mut Chunk) -> Vec<(u16, Cell)> {
+        let Some(cells) = self.pending_writes.lock().unwrap().remove(&chunk.chunkpos) else {
+            return Vec::new();
+        };
+        cells
+            .into_iter()
+            .filter(|(local_idx, cell)| set_feature_cell(chunk, Chunk::local_idx_pos(*local_idx as i32), cell))
+            .collect()
+    }
+
+    /// Apply pending feature cells to the already loaded chunks. return the changed cells of each chunk.
+    pub fn apply_pending_writes_loaded(&self, chunks: &HashMap<IVec3, ChunkPtr>) -> Vec<(IVec3, Vec<(u16, Cell)>)> {
+        let mut writes = Vec::new();
+        {
+            let mut pending_writes = self.pending_writes.lock().unwrap();
+            pending_writes.retain(|chunkpos, cells| {
+                if !chunks.contains_key(chunkpos) {
+                    return true;
+                }
+                writes.push((*chunkpos, std::mem::take(cells)));
+                false
+            });
+        }
+
+        // don't hold pending_writes while locking chunks.
+        writes
+            .into_iter()
+            .filter_map(|(chunkpos, cells)| {
+                let mut chunk = chunks[&chunkpos].write().unwrap();
+                let changed: Vec<_> = cells
+                    .into_iter()
+                    .filter(|(local_idx, cell)| set_feature_cell(&mut chunk, Chunk::local_idx_pos(*local_idx as i32), cell))
+                    .collect();
+                (!changed.is_empty()).then_some((chunkpos, changed))
+            })
+            .collect()
+    }
+
+    // return true if feature cells are deferred to other chunks.
+    fn populate_chunk(&self, chunk: &mut Chunk, columns: &[ColumnInfo]) -> bool {
         let chunkpos = chunk.chunkpos;
-        let perlin = Perlin::new(123);
+        let perlin = &self.perlin;
+        let fs = self.feature_seed;
+        let surface = &self.preset.surface;
+        let features = &self.preset.features;
+        let topsoil_depth = surface.surface_depth + surface.subsurface_depth;
 
         for lx in 0..Chunk::SIZE {
             for lz in 0..Chunk::SIZE {
+                let col = &columns[(lx * Chunk::SIZE + lz) as usize];
+                let def = col.biome.def();
                 let mut air_dist = 0;
 
                 for ly in (0..Chunk::SIZE).rev() {
@@ -70,12 +341,15 @@ impl WorldGen {
 
                     if c.tex_id == mtl::STONE {
                         let mut replace = c.tex_id;
-                        if p.y < 2 && air_dist <= 2 && perlin.get([p.x as f64 / 32., p.z as f64 / 32.]) > 0.1 {
+                        if (p.y as f32) < col.water_level + surface.shore_height
+                            && air_dist <= topsoil_depth
+                            && perlin.get([p.x as f64 / 32., p.z as f64 / 32.]) > surface.shore_threshold
+                        {
                             replace = mtl::SAND;
-                        } else if air_dist <= 1 {
-                            replace = mtl::GRASS;
-                        } else if air_dist < 3 {
-                            replace = mtl::DIRT;
+                        } else if air_dist <= surface.surface_depth {
+                            replace = def.surface;
+                        } else if air_dist <= topsoil_depth {
+                            replace = def.subsurface;
                         }
                         c.tex_id = replace;
                     }
@@ -85,52 +359,50 @@ impl WorldGen {
             }
         }
 
+        let mut writer = FeatureWriter::new(chunk);
+
         for lx in 0..Chunk::SIZE {
             for lz in 0..Chunk::SIZE {
                 let x = chunkpos.x + lx;
                 let z = chunkpos.z + lz;
+                let def = columns[(lx * Chunk::SIZE + lz) as usize].biome.def();
 
                 // Grass
                 // hash(x * z * 100) < 0.23
-                let g = perlin.get([x as f64 / 18., z as f64 / 18.]);
-                if g > 0.0 {
+                let g = perlin.get([x as f64 / features.grass_scale, z as f64 / features.grass_scale]) as f32;
+                if features.grass && g > def.grass_threshold {
                     for ly in 0..Chunk::SIZE - 1 {
                         let lp = ivec3(lx, ly, lz);
 
-                        if chunk.get_cell(lp).tex_id == mtl::GRASS && chunk.get_cell(lp + IVec3::Y).tex_id == 0 {
-                            let c = chunk.get_cell_mut(lp + IVec3::Y);
-                            c.tex_id = if g > 0.94 {
-                                mtl::ROSE
-                            } else if g > 0.8 {
-                                mtl::FERN
-                            } else if g > 0.24 {
-                                mtl::BUSH
+                        if writer.chunk.get_cell(lp).tex_id == def.surface && writer.chunk.get_cell(lp + IVec3::Y).tex_id == 0 {
+                            let bands = &features.foliage_bands;
+                            let tex_id = if g > bands[0] {
+                                def.foliage[0]
+                            } else if g > bands[1] {
+                                def.foliage[1]
+                            } else if g > bands[2] {
+                                def.foliage[2]
                             } else {
-                                mtl::SHORTGRASS
+                                def.foliage[3]
                             };
-                            c.shape_id = VoxShape::Grass;
+                            writer.set(lp + IVec3::Y, Cell::new(tex_id, VoxShape::Grass, 0.0));
                             break;
                         }
                     }
                 }
 
-                // Vines
-                if hash(x ^ (z * 7384)) < (18.0 / 256.0) {
+                // Vines. hang down through air, until the chunk bottom. cells of the chunk below can't be seen here.
+                if features.vines && hash(x ^ (z * 7384) ^ fs) < features.vine_chance {
                     for ly in 0..Chunk::SIZE - 1 {
                         let lp = ivec3(lx, ly, lz);
 
-                        if chunk.get_cell(lp).tex_id == 0 && chunk.get_cell(lp + IVec3::Y).tex_id == mtl::STONE {
-                            for i in 0..(12.0 * hash(x ^ (z * 121))) as i32 {
+                        if writer.chunk.get_cell(lp).tex_id == 0 && writer.chunk.get_cell(lp + IVec3::Y).tex_id == mtl::STONE {
+                            for i in 0..(features.vine_length * hash(x ^ (z * 121) ^ fs)) as i32 {
                                 let lp = lp + IVec3::NEG_Y * i;
-                                if lp.y < 0 {
-                                    break;
-                                }
-                                let c = chunk.get_cell_mut(lp);
-                                if c.tex_id != 0 {
+                                if !Chunk::is_localpos(lp) || writer.chunk.get_cell(lp).tex_id != 0 {
                                     break;
                                 }
-                                c.tex_id = mtl::LEAVES;
-                                c.shape_id = VoxShape::Leaves;
+                                writer.set(lp, Cell::new(mtl::LEAVES, VoxShape::Leaves, 0.0));
                             }
                             break;
                         }
@@ -138,23 +410,96 @@ impl WorldGen {
                 }
 
                 // Trees
-                if hash(x ^ (z * 9572)) < (3.0 / 256.0) {
+                let Some(tree) = def.tree.filter(|_| features.trees) else {
+                    continue;
+                };
+                if hash(x ^ (z * 9572) ^ fs) < (def.tree_chance * features.tree_density / 256.0) {
                     for ly in 0..Chunk::SIZE {
                         let lp = ivec3(lx, ly, lz);
 
-                        if chunk.get_cell(lp).tex_id != mtl::GRASS {
+                        if writer.chunk.get_cell(lp).tex_id != def.surface {
                             continue;
                         }
-                        let siz = hash(x ^ ly ^ z);
-                        gen_tree(chunk, lp, siz);
+                        let siz = hash(x ^ ly ^ z ^ fs);
+                        match tree {
+                            TreeKind::Oak => gen_tree(&mut writer, lp, siz),
+                            TreeKind::Spruce => gen_tree_spruce(&mut writer, lp, siz),
+                            TreeKind::Acacia => gen_tree_acacia(&mut writer, lp, siz),
+                        }
                     }
                 }
             }
         }
+
+        self.push_pending_writes(writer.outside)
+    }
+}
+
+// Feature cells only replace weaker cells. thus overlapping features resolve the same regardless of the generation order.
+fn feature_priority(c: &Cell) -> u8 {
+    if c.is_tex_empty() {
+        0
+    } else if c.shape_id == VoxShape::Grass {
+        1
+    } else if c.shape_id == VoxShape::Leaves {
+        2
+    } else if c.tex_id == mtl::LOG {
+        3
+    } else {
+        4
+    }
+}
+
+// return true if the cell is changed.
+fn set_feature_cell(chunk: &mut Chunk, lp: IVec3, cell: &Cell) -> bool {
+    let old = chunk.get_cell(lp);
+    if feature_priority(cell) <= feature_priority(old) {
+        return false;
+    }
+    chunk.set_cell(lp, cell);
+    true
+}
+
+/// Places feature cells at positions relative to the generating chunk.
+/// cells outside of the chunk are collected by their chunkpos, to be deferred as pending writes.
+pub struct FeatureWriter<'a> {
+    pub chunk: &'a mut Chunk,
+    pub outside: PendingWrites,
+}
+
+impl<'a> FeatureWriter<'a> {
+    pub fn new(chunk: &'a mut Chunk) -> Self {
+        Self {
+            chunk,
+            outside: HashMap::default(),
+        }
+    }
+
+    pub fn set(&mut self, lp: IVec3, cell: Cell) {
+        if Chunk::is_localpos(lp) {
+            set_feature_cell(self.chunk, lp, &cell);
+        } else {
+            let p = self.chunk.chunkpos + lp;
+            let local_idx = Chunk::local_idx(Chunk::as_localpos(p)) as u16;
+            self.outside.entry(Chunk::as_chunkpos(p)).or_default().push((local_idx, cell));
+        }
+    }
+
+    fn set_leaves(&mut self, lp: IVec3) {
+        self.set(lp, Cell::new(mtl::LEAVES, VoxShape::Leaves, 0.0));
+    }
+
+    fn set_trunk(&mut self, lp: IVec3, isovalue: f32) {
+        self.set(lp, Cell::new(mtl::LOG, VoxShape::Isosurface, isovalue));
+    }
+
+    // the root of a tree replaces the surface cell it grows on. always in the generating chunk.
+    fn set_root(&mut self, lp: IVec3, isovalue: f32) {
+        self.chunk.set_cell(lp, &Cell::new(mtl::LOG, VoxShape::Isosurface, isovalue));
     }
 }
 
-pub fn gen_tree(chunk: &mut Chunk, lp: IVec3, siz: f32) {
+pub fn gen_tree(writer: &mut FeatureWriter, lp: IVec3, siz: f32) {
     let trunk_height = 3 + (siz * 6.0) as i32;
     let leaves_rad = 2 + (siz * 5.0) as i32;
 
@@ -163,23 +508,52 @@ pub fn gen_tree(chunk: &mut Chunk, lp: IVec3, siz: f32) {
         if rp.length_squared() >= leaves_rad * leaves_rad {
             return;
         }
-        let lp = lp + IVec3::Y * trunk_height + rp;
-        if !Chunk::is_localpos(lp) {
-            return;
-        }
-        let c = chunk.get_cell_mut(lp);
-        c.tex_id = mtl::LEAVES;
-        c.shape_id = VoxShape::Leaves;
+        writer.set_leaves(lp + IVec3::Y * trunk_height + rp);
     });
 
     // Trunk
-    for i in 0..trunk_height {
-        if i + lp.y > 15 {
-            break;
-        }
-        let c = chunk.get_cell_mut(lp + IVec3::Y * i);
-        c.tex_id = mtl::LOG;
-        c.shape_id = VoxShape::Isosurface;
-        c.set_isovalue(2.0 * (1.2 - i as f32 / trunk_height as f32));
+    writer.set_root(lp, 2.0 * 1.2);
+    for i in 1..trunk_height {
+        writer.set_trunk(lp + IVec3::Y * i, 2.0 * (1.2 - i as f32 / trunk_height as f32));
+    }
+}
+
+// Tall and thin, with cone leaves.
+pub fn gen_tree_spruce(writer: &mut FeatureWriter, lp: IVec3, siz: f32) {
+    let trunk_height = 6 + (siz * 6.0) as i32;
+    let leaves_bottom = 2;
+
+    for y in leaves_bottom..=trunk_height + 1 {
+        let rad = ((trunk_height + 1 - y) as f32 / (trunk_height + 1 - leaves_bottom) as f32 * (2.0 + siz * 2.0)).ceil() as i32;
+        iter::iter_aabb(rad, 0, |rp| {
+            if rp.x * rp.x + rp.z * rp.z <= rad * rad {
+                writer.set_leaves(lp + IVec3::Y * y + rp);
+            }
+        });
+    }
+
+    writer.set_root(lp, 1.6 * 1.2);
+    for i in 1..trunk_height {
+        writer.set_trunk(lp + IVec3::Y * i, 1.6 * (1.2 - i as f32 / trunk_height as f32));
+    }
+}
+
+// Short trunk with a flat, wide canopy.
+pub fn gen_tree_acacia(writer: &mut FeatureWriter, lp: IVec3, siz: f32) {
+    let trunk_height = 4 + (siz * 3.0) as i32;
+    let leaves_rad = 3 + (siz * 2.0) as i32;
+
+    for y in 0..2 {
+        let rad = leaves_rad - y;
+        iter::iter_aabb(rad, 0, |rp| {
+            if rp.x * rp.x + rp.z * rp.z <= rad * rad {
+                writer.set_leaves(lp + IVec3::Y * (trunk_height + y) + rp);
+            }
+        });
+    }
+
+    writer.set_root(lp, 1.6 * 1.2);
+    for i in 1..trunk_height {
+        writer.set_trunk(lp + IVec3::Y * i, 1.6 * (1.2 - i as f32 / trunk_height as f32));
     }
 }
@@ -0,0 +1,469 @@
+use bevy::{math::vec3, prelude::*};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+mod overlapping;
+mod tileset;
+pub use overlapping::OverlappingModel;
+pub use tileset::{Symmetry, TileDef, Tileset};
+
+pub struct SocketId {
+    pub shape_id: u16,
+    pub is_flip: bool,
+}
+
+pub struct Pattern {
+    pub name: String,
+
+    pub is_flipped: bool,
+    pub rotation: u8,
+
+    pub sockets: [u32; 6],
+
+    // relative frequency of being collapsed to.
+    pub weight: f32,
+}
+impl Pattern {
+    pub fn new(name: String, sockets: [u32; 6], rotation: u8) -> Self {
+        Self {
+            name,
+            is_flipped: false,
+            rotation,
+            sockets,
+            weight: 1.0,
+        }
+    }
+}
+
+fn sockets_rotate_y(sockets: [u32; 6]) -> [u32; 6] {
+    [sockets[4], sockets[5], sockets[2], sockets[3], sockets[1], sockets[0]]
+}
+
+fn sockets_flip_x(sockets: [u32; 6]) -> [u32; 6] {
+    [sockets[1], sockets[0], sockets[2], sockets[3], sockets[4], sockets[5]]
+}
+
+pub struct Tile {
+    pub pos: IVec3,
+    pub neighbors: [bool; 6],
+    pub possib: Vec<u16>, // superpatterns: Bitset<D>, coefficient
+}
+impl Tile {
+    const DIR: [Vec3; 6] = [
+        // 6 Faces
+        vec3(-1., 0., 0.),
+        vec3(1., 0., 0.),
+        vec3(0., -1., 0.),
+        vec3(0., 1., 0.),
+        vec3(0., 0., -1.),
+        vec3(0., 0., 1.),
+    ];
+    fn opposite_dir_idx(i: usize) -> usize {
+        i / 2 * 2 + (i + 1) % 2
+    }
+
+    fn new(pos: IVec3, pat: u16) -> Self {
+        Self {
+            pos,
+            neighbors: [false; 6],
+            possib: (0..pat).collect(),
+        }
+    }
+
+    fn is_collapsed(&self) -> bool {
+        self.entropy() == 1
+    }
+
+    // num superpatterns, num coefficients
+    pub fn entropy(&self) -> usize {
+        self.possib.len()
+    }
+
+    // Shannon entropy of the pattern weights. fewer and more biased possibilities are lower.
+    fn weighted_entropy(&self, all_pat: &[Pattern]) -> f32 {
+        let mut sum_weights = 0.0;
+        let mut sum_weights_log_weights = 0.0;
+        for possib in self.possib.iter() {
+            let weight = all_pat[*possib as usize].weight;
+            if weight > 0.0 {
+                sum_weights += weight;
+                sum_weights_log_weights += weight * weight.ln();
+            }
+        }
+        if sum_weights <= 0.0 {
+            return 0.0;
+        }
+        sum_weights.ln() - sum_weights_log_weights / sum_weights
+    }
+
+    // pick a pattern by weights. return the chosen pattern.
+    fn collapse(&mut self, rng: &mut impl Rng, all_pat: &[Pattern]) -> u16 {
+        assert!(!self.is_collapsed());
+
+        let sum_weights: f32 = self.possib.iter().map(|possib| all_pat[*possib as usize].weight).sum();
+        let tmp = if sum_weights > 0.0 {
+            let mut r = rng.gen_range(0.0..sum_weights);
+            *self
+                .possib
+                .iter()
+                .find(|possib| {
+                    r -= all_pat[**possib as usize].weight;
+                    r < 0.0
+                })
+                .unwrap_or(self.possib.last().unwrap())
+        } else {
+            // all zero weights, uniformly.
+            self.possib[rng.gen_range(0..self.possib.len())]
+        };
+        self.possib.clear();
+        self.possib.push(tmp);
+        tmp
+    }
+
+    // fn get_collapsed_pattern() -> &Pattern {
+
+    // }
+
+    // return: the old possibilities if changed so keep propagate, None is non change so skip propagate.
+    // collapsed tiles are constrained too, a collapsed tile left with no possibility is a contradiction.
+    fn constrain(&mut self, oppo_possib: &[u16], dir_idx: usize, all_pat: &[Pattern], rules: &[[Vec<u16>; 6]]) -> Option<Vec<u16>> {
+        let new_possib: Vec<u16> = if rules.is_empty() {
+            let mut sockets = Vec::new();
+            for possib in oppo_possib {
+                sockets.push(all_pat[*possib as usize].sockets[dir_idx]);
+            }
+            self.possib
+                .iter()
+                .copied()
+                .filter(|possib| sockets.contains(&all_pat[*possib as usize].sockets[Tile::opposite_dir_idx(dir_idx)]))
+                .collect()
+        } else {
+            let mut allowed = vec![false; all_pat.len()];
+            for possib in oppo_possib {
+                for neib in rules[*possib as usize][dir_idx].iter() {
+                    allowed[*neib as usize] = true;
+                }
+            }
+            self.possib.iter().copied().filter(|possib| allowed[*possib as usize]).collect()
+        };
+
+        if new_possib.len() == self.entropy() {
+            return None;
+        }
+        Some(std::mem::replace(&mut self.possib, new_possib))
+    }
+}
+
+fn idx_3d_pos_inbound(pos: IVec3, extent: IVec3) -> bool {
+    pos.min_element() >= 0 && pos.x < extent.x && pos.y < extent.y && pos.z < extent.z
+}
+fn idx_3d(pos: IVec3, extent: IVec3) -> usize {
+    (pos.x + extent.x * (pos.y + extent.y * pos.z)) as usize
+}
+fn idx_3d_reveal(idx: i32, extent: IVec3) -> IVec3 {
+    let z = idx / (extent.x * extent.y);
+    let y = (idx - z * extent.x * extent.y) / extent.x;
+    let x = idx - z * extent.x * extent.y - y * extent.x;
+    IVec3::new(x, y, z)
+}
+
+/// The solver ran into a contradiction, in every attempt. Tiles are left as the last attempt ended.
+#[derive(Debug, Clone)]
+pub struct Contradiction {
+    /// the tile left with no possible pattern, and the neighbor constraining it.
+    pub tiles: Vec<IVec3>,
+    pub attempts: u32,
+}
+
+impl std::fmt::Display for Contradiction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WFC contradiction at tiles {:?} after {} attempts", self.tiles, self.attempts)
+    }
+}
+
+impl std::error::Error for Contradiction {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Solved {
+    pub attempts: u32,
+    pub backtracks: u32,
+}
+
+pub type WfcResult = Result<Solved, Contradiction>;
+
+// a collapse that can be undone, by restoring the trail back to the length before the collapse.
+struct Decision {
+    trail_len: usize,
+    tile_idx: usize,
+    pattern: u16,
+}
+
+pub struct WFC {
+    pub extent: IVec3,
+    pub tiles: Vec<Tile>,
+
+    pub all_patterns: Vec<Pattern>,
+
+    /// Explicit adjacency rules. patterns allowed next to each pattern, at each face. [-X, +X, -Y, +Y, -Z, +Z]
+    /// Empty uses sockets, patterns are allowed when their facing sockets are equal.
+    pub rules: Vec<[Vec<u16>; 6]>,
+
+    // seed of `run`. the same seed, patterns and constraints always give the same result.
+    pub seed: u64,
+
+    // restart from the initial state with the continued rng, up to this many attempts.
+    pub max_attempts: u32,
+    // undo collapses on contradiction, up to this many times per attempt before restarting.
+    pub max_backtracks: u32,
+
+    // old possibilities of changed tiles, for undoing collapses.
+    trail: Vec<(usize, Vec<u16>)>,
+}
+
+impl Default for WFC {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WFC {
+    pub fn new() -> Self {
+        Self {
+            extent: IVec3::ZERO,
+            tiles: Vec::new(),
+            all_patterns: Vec::new(),
+            rules: Vec::new(),
+            seed: rand::random(),
+            max_attempts: 8,
+            max_backtracks: 64,
+            trail: Vec::new(),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn push_pattern(&mut self, name: String, sockets: [u32; 6], rot: bool, flip: bool) {
+        self.push_pattern_weighted(name, sockets, rot, flip, 1.0);
+    }
+
+    /// Push the pattern and its rotated (around Y) / flipped (along X) variants. each variant has the weight.
+    pub fn push_pattern_weighted(&mut self, name: String, sockets: [u32; 6], rot: bool, flip: bool, weight: f32) {
+        for is_flipped in [false, true] {
+            if is_flipped && !flip {
+                continue;
+            }
+            // flip first, then rotate.
+            let mut sockets = if is_flipped { sockets_flip_x(sockets) } else { sockets };
+            for rotation in 0..if rot { 4 } else { 1 } {
+                self.all_patterns.push(Pattern {
+                    name: name.clone(),
+                    is_flipped,
+                    rotation,
+                    sockets,
+                    weight: weight.max(0.0),
+                });
+                sockets = sockets_rotate_y(sockets);
+            }
+        }
+    }
+
+    pub fn push_tileset(&mut self, tileset: &Tileset) {
+        for tile in tileset.tiles.iter() {
+            self.push_pattern_weighted(tile.name.clone(), tile.sockets, tile.symmetry.rotate(), tile.symmetry.flip(), tile.weight);
+        }
+    }
+
+    pub fn init_tiles(&mut self, extent: IVec3) {
+        self.extent = extent;
+
+        let num_tiles = (extent.x * extent.y * extent.z) as usize;
+        self.tiles.reserve(num_tiles);
+
+        for idx in 0..num_tiles {
+            let p = idx_3d_reveal(idx as i32, extent);
+            self.tiles.push(Tile::new(p, self.all_patterns.len() as u16));
+        }
+    }
+
+    pub fn get_tile(&self, pos: IVec3) -> Option<&Tile> {
+        if !idx_3d_pos_inbound(pos, self.extent) {
+            return None;
+        }
+        Some(&self.tiles[idx_3d(pos, self.extent)])
+    }
+    pub fn get_tile_mut(&mut self, pos: IVec3) -> Option<&mut Tile> {
+        if !idx_3d_pos_inbound(pos, self.extent) {
+            return None;
+        }
+        Some(&mut self.tiles[idx_3d(pos, self.extent)])
+    }
+
+    /// Run with a rng of `seed`.
+    pub fn run(&mut self) -> WfcResult {
+        self.run_with_rng(&mut StdRng::seed_from_u64(self.seed))
+    }
+
+    /// Run with the given rng. a seeded rng makes the result deterministic.
+    /// On contradiction, recent collapses are undone (bounded by `max_backtracks`), then the whole solve restarts (up to `max_attempts`).
+    pub fn run_with_rng(&mut self, rng: &mut impl Rng) -> WfcResult {
+        assert!(self.rules.is_empty() || self.rules.len() == self.all_patterns.len(), "rules don't match patterns");
+
+        // contradicted by constraints, no attempt can solve it.
+        let conflicts: Vec<IVec3> = self.tiles.iter().filter(|t| t.entropy() == 0).map(|t| t.pos).collect();
+        if !conflicts.is_empty() {
+            return Err(Contradiction {
+                tiles: conflicts,
+                attempts: 0,
+            });
+        }
+
+        let initial = self.snapshot();
+        let mut backtracks = 0;
+        let mut conflicts = Vec::new();
+        let max_attempts = self.max_attempts.max(1);
+        for attempt in 1..=max_attempts {
+            if attempt > 1 {
+                self.restore(&initial);
+            }
+            match self.solve(rng, &mut backtracks) {
+                Ok(()) => return Ok(Solved { attempts: attempt, backtracks }),
+                Err(tiles) => conflicts = tiles,
+            }
+        }
+        Err(Contradiction {
+            tiles: conflicts,
+            attempts: max_attempts,
+        })
+    }
+
+    fn solve(&mut self, rng: &mut impl Rng, backtracks: &mut u32) -> Result<(), Vec<IVec3>> {
+        let mut decisions: Vec<Decision> = Vec::new();
+        let mut num_backtracks = 0;
+        self.trail.clear();
+
+        while let Some(tile_idx) = self.next_tile_to_observe() {
+            let trail_len = self.trail.len();
+            self.trail.push((tile_idx, self.tiles[tile_idx].possib.clone()));
+            let pattern = self.tiles[tile_idx].collapse(rng, &self.all_patterns);
+            decisions.push(Decision { trail_len, tile_idx, pattern });
+
+            let mut result = self.propagate(tile_idx);
+
+            // Backtrack. undo the latest collapse and exclude its pattern, until it's consistent again.
+            while let Err(conflicts) = result {
+                let Some(decision) = decisions.pop().filter(|_| num_backtracks < self.max_backtracks) else {
+                    return Err(conflicts);
+                };
+                num_backtracks += 1;
+                *backtracks += 1;
+
+                self.undo(decision.trail_len);
+                self.trail.push((decision.tile_idx, self.tiles[decision.tile_idx].possib.clone()));
+                let tile = &mut self.tiles[decision.tile_idx];
+                tile.possib.retain(|possib| *possib != decision.pattern);
+                result = if tile.entropy() == 0 {
+                    Err(vec![tile.pos])
+                } else {
+                    self.propagate(decision.tile_idx)
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Vec<Vec<u16>> {
+        self.tiles.iter().map(|t| t.possib.clone()).collect()
+    }
+
+    fn restore(&mut self, snapshot: &[Vec<u16>]) {
+        for (tile, possib) in self.tiles.iter_mut().zip(snapshot) {
+            tile.possib.clone_from(possib);
+        }
+    }
+
+    fn undo(&mut self, trail_len: usize) {
+        while self.trail.len() > trail_len {
+            let (tile_idx, possib) = self.trail.pop().unwrap();
+            self.tiles[tile_idx].possib = possib;
+        }
+    }
+
+    /// Restrict possibilities of the tile to patterns satisfying `allowed`, before `run`.
+    /// Contradicting constraints are reported by `run`.
+    pub fn constrain_tile(&mut self, pos: IVec3, allowed: impl Fn(&Pattern) -> bool) {
+        let tile_idx = idx_3d(pos, self.extent);
+        let all_pat = &self.all_patterns;
+        self.tiles[tile_idx].possib.retain(|possib| allowed(&all_pat[*possib as usize]));
+
+        let _ = self.propagate(tile_idx);
+    }
+
+    /// Restrict tiles on the boundary to patterns with `socket` on their outward faces, before `run`.
+    /// e.g. an empty socket, so the result doesn't get cut off at the boundary.
+    pub fn constrain_boundary(&mut self, socket: u32) {
+        for tile_idx in 0..self.tiles.len() {
+            let pos = self.tiles[tile_idx].pos;
+            let all_pat = &self.all_patterns;
+            let old_len = self.tiles[tile_idx].entropy();
+
+            for (dir_idx, dir) in Tile::DIR.iter().enumerate() {
+                if !idx_3d_pos_inbound(pos + dir.as_ivec3(), self.extent) {
+                    self.tiles[tile_idx].possib.retain(|possib| all_pat[*possib as usize].sockets[dir_idx] == socket);
+                }
+            }
+            if old_len != self.tiles[tile_idx].entropy() {
+                let _ = self.propagate(tile_idx);
+            }
+        }
+    }
+
+    // find next tile to collapse/observe. used Minimal-Entropy Heuristic here, due to human sence / predictability / stability
+    fn next_tile_to_observe(&self) -> Option<usize> {
+        let mut ret = None;
+        let mut min = f32::MAX;
+        for (idx, tile) in self.tiles.iter().enumerate() {
+            if tile.is_collapsed() || tile.entropy() == 0 {
+                continue;
+            }
+            let n = tile.weighted_entropy(&self.all_patterns);
+            if n < min {
+                min = n;
+                ret = Some(idx);
+            }
+        }
+        ret
+    }
+
+    // return Err with the contradicting tiles, if a tile is left with no possibility.
+    fn propagate(&mut self, tile_idx: usize) -> Result<(), Vec<IVec3>> {
+        // DFS
+        let mut stack = Vec::new();
+        stack.push(tile_idx);
+
+        while let Some(tile_idx) = stack.pop() {
+            let pos = self.tiles[tile_idx].pos;
+            let possib = self.tiles[tile_idx].possib.clone();
+
+            for (dir_idx, neib_dir) in Tile::DIR.iter().enumerate() {
+                let neib_pos = pos + neib_dir.as_ivec3();
+                if !idx_3d_pos_inbound(neib_pos, self.extent) {
+                    continue;
+                }
+                let neib_idx = idx_3d(neib_pos, self.extent);
+
+                let neib_tile = &mut self.tiles[neib_idx];
+                if let Some(old_possib) = neib_tile.constrain(&possib, dir_idx, &self.all_patterns, &self.rules) {
+                    self.trail.push((neib_idx, old_possib));
+                    if self.tiles[neib_idx].entropy() == 0 {
+                        return Err(vec![neib_pos, pos]);
+                    }
+                    // propagate changed value
+                    stack.push(neib_idx); // when possibilities reduced need to propagate further.
+                }
+            }
+        }
+        Ok(())
+    }
+}
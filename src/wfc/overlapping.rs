@@ -0,0 +1,200 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use super::{Contradiction, Tile, WFC};
+use crate::voxel::{Cell, ChunkSystem, VoxShape};
+
+/// Overlapping Model. learns NxNxN patterns of cells from an example region, and which patterns may overlap
+/// each other at an offset of 1 cell. then synthesizes regions of a similar style, locally like the example.
+pub struct OverlappingModel {
+    pub n: i32,
+
+    // distinct cells of the example. patterns are indices of it.
+    palette: Vec<Cell>,
+    // cells of each pattern, indexed `(y * n + z) * n + x`.
+    patterns: Vec<Vec<u16>>,
+    // number of occurrences in the example.
+    weights: Vec<f32>,
+    rules: Vec<[Vec<u16>; 6]>,
+}
+
+// Cells are compared by material and shape, isovalues are snapped to solid/empty.
+// otherwise smooth terrain would hardly have 2 equal patterns.
+fn normalize_cell(c: Cell) -> Cell {
+    let mut c = c;
+    c.set_isovalue(if c.isovalue() > 0.0 { 0.5 } else { -0.5 });
+    c
+}
+
+// directional shapes turned along with the pattern, see `rotate_pos`.
+fn rotate_shape(shape: VoxShape) -> VoxShape {
+    match shape {
+        VoxShape::SlabXMin => VoxShape::SlabZMin,
+        VoxShape::SlabZMin => VoxShape::SlabXMax,
+        VoxShape::SlabXMax => VoxShape::SlabZMax,
+        VoxShape::SlabZMax => VoxShape::SlabXMin,
+        VoxShape::CustomMesh { mesh_id, rotation } => VoxShape::CustomMesh {
+            mesh_id,
+            rotation: (rotation + 1) % 4,
+        },
+        _ => shape,
+    }
+}
+
+// 90 degrees around Y, in a NxNxN pattern.
+fn rotate_pos(p: IVec3, n: i32) -> IVec3 {
+    IVec3::new(n - 1 - p.z, p.y, p.x)
+}
+
+fn idx_cube(p: IVec3, n: i32) -> usize {
+    ((p.y * n + p.z) * n + p.x) as usize
+}
+
+impl OverlappingModel {
+    /// Learn from the example region `min..=max` of the loaded chunks. `rotate` also learns 3 rotations around Y of the example.
+    pub fn learn(chunk_sys: &impl ChunkSystem, min: IVec3, max: IVec3, n: i32, rotate: bool) -> anyhow::Result<Self> {
+        let size = max - min + 1;
+        if n < 2 || size.min_element() < n {
+            anyhow::bail!("the example region {} is smaller than the pattern size {}", size, n);
+        }
+        if size.x * size.y * size.z > 32 * 32 * 32 {
+            anyhow::bail!("the example region {} is too large", size);
+        }
+
+        let mut palette: Vec<Cell> = Vec::new();
+        let mut example = Vec::with_capacity((size.x * size.y * size.z) as usize);
+        for y in 0..size.y {
+            for z in 0..size.z {
+                for x in 0..size.x {
+                    let p = min + IVec3::new(x, y, z);
+                    let Some(c) = chunk_sys.get_cell(p) else {
+                        anyhow::bail!("the chunk of {} is not loaded", p);
+                    };
+                    let c = normalize_cell(c);
+                    let i = palette.iter().position(|e| *e == c).unwrap_or_else(|| {
+                        palette.push(c);
+                        palette.len() - 1
+                    });
+                    example.push(i as u16);
+                }
+            }
+        }
+        let at = |p: IVec3| example[((p.y * size.z + p.z) * size.x + p.x) as usize];
+
+        // Extract patterns of every window of the example, weighted by occurrences.
+        let mut pattern_idx: HashMap<Vec<u16>, usize> = HashMap::default();
+        let mut patterns = Vec::new();
+        let mut weights = Vec::new();
+        let cube = (n * n * n) as usize;
+        for oy in 0..=size.y - n {
+            for oz in 0..=size.z - n {
+                for ox in 0..=size.x - n {
+                    let mut pat: Vec<u16> = (0..cube).map(|i| at(IVec3::new(ox, oy, oz) + idx_cube_reveal(i, n))).collect();
+
+                    for _ in 0..if rotate { 4 } else { 1 } {
+                        let idx = *pattern_idx.entry(pat.clone()).or_insert_with(|| {
+                            patterns.push(pat.clone());
+                            weights.push(0.0);
+                            patterns.len() - 1
+                        });
+                        weights[idx] += 1.0;
+
+                        if rotate {
+                            pat = Self::rotate_pattern(&pat, n, &mut palette);
+                        }
+                    }
+                }
+            }
+        }
+        if patterns.len() > u16::MAX as usize {
+            anyhow::bail!("too many patterns {}", patterns.len());
+        }
+
+        // Adjacency. b may be at a + dir if they agree on the overlapping cells.
+        // patterns are grouped by their overlapping cells instead of comparing every pair.
+        let mut rules: Vec<[Vec<u16>; 6]> = vec![Default::default(); patterns.len()];
+        for (dir_idx, dir) in Tile::DIR.iter().enumerate() {
+            let dir = dir.as_ivec3();
+            let mut by_overlap: HashMap<Vec<u16>, Vec<u16>> = HashMap::default();
+            for (b, pat) in patterns.iter().enumerate() {
+                by_overlap.entry(Self::overlap(pat, dir, n, false)).or_default().push(b as u16);
+            }
+            for (a, pat) in patterns.iter().enumerate() {
+                rules[a][dir_idx] = by_overlap.get(&Self::overlap(pat, dir, n, true)).cloned().unwrap_or_default();
+            }
+        }
+
+        Ok(Self {
+            n,
+            palette,
+            patterns,
+            weights,
+            rules,
+        })
+    }
+
+    fn rotate_pattern(pat: &[u16], n: i32, palette: &mut Vec<Cell>) -> Vec<u16> {
+        let mut rotated = vec![0; pat.len()];
+        for (i, cell_idx) in pat.iter().enumerate() {
+            let mut c = palette[*cell_idx as usize];
+            c.shape_id = rotate_shape(c.shape_id);
+            let cell_idx = palette.iter().position(|e| *e == c).unwrap_or_else(|| {
+                palette.push(c);
+                palette.len() - 1
+            });
+            rotated[idx_cube(rotate_pos(idx_cube_reveal(i, n), n), n)] = cell_idx as u16;
+        }
+        rotated
+    }
+
+    // Cells of the overlap of a pattern `a` and a pattern `b` placed at a + dir, in the order of b's cells.
+    // `as_a` takes the cells of the pattern as `a`, otherwise as `b`. a and b agree if their overlaps are equal.
+    fn overlap(pat: &[u16], dir: IVec3, n: i32, as_a: bool) -> Vec<u16> {
+        (0..pat.len())
+            .filter_map(|i| {
+                let pb = idx_cube_reveal(i, n);
+                let pa = pb + dir;
+                if pa.min_element() < 0 || pa.max_element() >= n {
+                    return None;
+                }
+                Some(if as_a { pat[idx_cube(pa, n)] } else { pat[i] })
+            })
+            .collect()
+    }
+
+    pub fn num_patterns(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Synthesize a region of `extent` cells. cells are indexed `(y * extent.z + z) * extent.x + x`.
+    /// The same model and seed always give the same result.
+    pub fn generate(&self, extent: IVec3, seed: u64) -> Result<Vec<Cell>, Contradiction> {
+        let n = self.n;
+        // each tile is the min corner of a pattern. the patterns of the last tiles cover the rest of the cells.
+        let grid = (extent - (n - 1)).max(IVec3::ONE);
+
+        let mut wfc = WFC::new().with_seed(seed);
+        for (i, weight) in self.weights.iter().enumerate() {
+            wfc.push_pattern_weighted(i.to_string(), [0; 6], false, false, *weight);
+        }
+        wfc.rules = self.rules.clone();
+        wfc.init_tiles(grid);
+        wfc.run()?;
+
+        let mut cells = vec![Cell::default(); (extent.x * extent.y * extent.z) as usize];
+        for tile in wfc.tiles.iter() {
+            let pat = &self.patterns[tile.possib[0] as usize];
+            for (i, cell_idx) in pat.iter().enumerate() {
+                let p = tile.pos + idx_cube_reveal(i, n);
+                if p.cmplt(extent).all() {
+                    cells[((p.y * extent.z + p.z) * extent.x + p.x) as usize] = self.palette[*cell_idx as usize];
+                }
+            }
+        }
+        Ok(cells)
+    }
+}
+
+fn idx_cube_reveal(i: usize, n: i32) -> IVec3 {
+    let i = i as i32;
+    IVec3::new(i % n, i / (n * n), i / n % n)
+}
@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Variants of a tile pushed as patterns.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum Symmetry {
+    /// only the tile as it is.
+    #[default]
+    Fixed,
+    /// and its 3 rotations around Y.
+    Rotate,
+    /// and its mirror along X.
+    Flip,
+    /// all 8 rotated and mirrored variants.
+    RotateFlip,
+}
+
+impl Symmetry {
+    pub fn rotate(self) -> bool {
+        matches!(self, Symmetry::Rotate | Symmetry::RotateFlip)
+    }
+    pub fn flip(self) -> bool {
+        matches!(self, Symmetry::Flip | Symmetry::RotateFlip)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileDef {
+    pub name: String,
+    // socket ids of the faces. [-X, +X, -Y, +Y, -Z, +Z], adjacent faces must have the same id.
+    pub sockets: [u32; 6],
+    pub symmetry: Symmetry,
+    // relative frequency. every variant of the tile has this weight.
+    pub weight: f32,
+    // asset of the tile, e.g. a texture or a model. not used by the solver.
+    pub asset: Option<String>,
+}
+
+impl Default for TileDef {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            sockets: [0; 6],
+            symmetry: Symmetry::Fixed,
+            weight: 1.0,
+            asset: None,
+        }
+    }
+}
+
+/// WFC Tileset. loaded from `*.tileset.json` assets, so tilesets can be authored without recompiling.
+#[derive(Asset, TypePath, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tileset {
+    pub tiles: Vec<TileDef>,
+}
+
+impl Tileset {
+    pub fn get(&self, name: &str) -> Option<&TileDef> {
+        self.tiles.iter().find(|t| t.name == name)
+    }
+}